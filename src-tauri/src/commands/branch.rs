@@ -0,0 +1,300 @@
+use git2::{Branch, BranchType, ErrorCode, Repository};
+use logging_timer::time;
+use serde::Serialize;
+
+use super::{checkout_reference, find_commit};
+
+#[derive(Serialize)]
+pub enum BranchError {
+    Read(String),
+    InvalidName,
+    AlreadyExists,
+    NotFound,
+    // The revspec to create the branch on doesn't point to a commit
+    InvalidRevision,
+    // The checked out branch can't be deleted
+    IsHead,
+    // It has commits that aren't in its upstream (or HEAD if it doesn't have one)
+    NotFullyMerged,
+    // Checking it out would overwrite local changes
+    Conflict,
+}
+
+impl From<git2::Error> for BranchError {
+    fn from(value: git2::Error) -> Self {
+        match value.code() {
+            ErrorCode::Exists => BranchError::AlreadyExists,
+            ErrorCode::InvalidSpec => BranchError::InvalidName,
+            ErrorCode::NotFound => BranchError::NotFound,
+            ErrorCode::Conflict => BranchError::Conflict,
+            _ => BranchError::Read(value.message().to_owned()),
+        }
+    }
+}
+
+/// Creates the branch `name` on `revspec`, which can be anything that points to a commit.
+/// If it can't be checked out, it doesn't get created either.
+#[time]
+#[tauri::command(async)]
+pub fn create_branch(
+    path: String,
+    name: String,
+    revspec: String,
+    checkout: bool,
+) -> Result<(), BranchError> {
+    let repo = Repository::open(path)?;
+    if !Branch::name_is_valid(&name)? {
+        return Err(BranchError::InvalidName);
+    }
+
+    let commit = find_commit(&repo, &revspec).map_err(|e| match e.code() {
+        ErrorCode::NotFound | ErrorCode::InvalidSpec | ErrorCode::Ambiguous => {
+            BranchError::InvalidRevision
+        }
+        _ => e.into(),
+    })?;
+    let mut branch = repo.branch(&name, &commit, false)?;
+
+    if checkout {
+        if let Err(e) = checkout_reference(&repo, branch.get()) {
+            // Same as `git checkout -b`, the branch isn't left behind if it can't be switched to
+            branch.delete().ok();
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// The reflog gets moved along with the branch, and HEAD follows it if it was checked out.
+#[time]
+#[tauri::command(async)]
+pub fn rename_branch(path: String, name: String, new_name: String) -> Result<(), BranchError> {
+    let repo = Repository::open(path)?;
+    if !Branch::name_is_valid(&new_name)? {
+        return Err(BranchError::InvalidName);
+    }
+
+    repo.find_branch(&name, BranchType::Local)?
+        .rename(&new_name, false)?;
+
+    Ok(())
+}
+
+/// Like `git branch -d`, or `git branch -D` with `force`.
+#[time]
+#[tauri::command(async)]
+pub fn delete_branch(path: String, name: String, force: bool) -> Result<(), BranchError> {
+    let repo = Repository::open(path)?;
+    let mut branch = repo.find_branch(&name, BranchType::Local)?;
+    if branch.is_head() {
+        return Err(BranchError::IsHead);
+    }
+
+    if !force && !is_merged(&repo, &branch) {
+        return Err(BranchError::NotFullyMerged);
+    }
+
+    branch.delete()?;
+
+    Ok(())
+}
+
+fn is_merged(repo: &Repository, branch: &Branch) -> bool {
+    let tip = match branch.get().target() {
+        Some(tip) => tip,
+        None => return true,
+    };
+    let merged_into = branch
+        .upstream()
+        .ok()
+        .and_then(|upstream| upstream.get().target())
+        .or_else(|| repo.head().ok().and_then(|head| head.target()));
+
+    match merged_into {
+        Some(target) => target == tip || repo.graph_descendant_of(target, tip).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// `upstream` is the shorthand of a remote branch, e.g. `origin/main`. `None` unsets it.
+#[time]
+#[tauri::command(async)]
+pub fn set_upstream(
+    path: String,
+    name: String,
+    upstream: Option<String>,
+) -> Result<(), BranchError> {
+    let repo = Repository::open(path)?;
+
+    repo.find_branch(&name, BranchType::Local)?
+        .set_upstream(upstream.as_deref())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, Signature};
+    use tempfile::TempDir;
+
+    // A repo with `main` checked out
+    fn setup() -> (TempDir, Repository, String) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        commit(&repo, Some("HEAD"), &[]);
+        let path = dir.path().to_str().unwrap().to_owned();
+        (dir, repo, path)
+    }
+
+    fn commit(repo: &Repository, update_ref: Option<&str>, parents: &[Oid]) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let parents = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect::<Vec<_>>();
+        repo.commit(
+            update_ref,
+            &signature,
+            &signature,
+            "commit",
+            &tree,
+            &parents.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn head(repo: &Repository) -> Oid {
+        repo.head().unwrap().target().unwrap()
+    }
+
+    fn branch_tip(repo: &Repository, name: &str) -> Option<Oid> {
+        repo.find_branch(name, BranchType::Local)
+            .ok()
+            .and_then(|branch| branch.get().target())
+    }
+
+    #[test]
+    fn creates_branches() {
+        let (_dir, repo, path) = setup();
+
+        create_branch(path.clone(), "feature".to_owned(), "main".to_owned(), false)
+            .ok()
+            .unwrap();
+        assert_eq!(branch_tip(&repo, "feature"), Some(head(&repo)));
+        assert_eq!(repo.head().unwrap().shorthand(), Some("main"));
+
+        create_branch(path, "other".to_owned(), "HEAD".to_owned(), true)
+            .ok()
+            .unwrap();
+        assert_eq!(repo.head().unwrap().shorthand(), Some("other"));
+    }
+
+    #[test]
+    fn refuses_to_create_invalid_branches() {
+        let (_dir, _repo, path) = setup();
+
+        let result = create_branch(path.clone(), "feature".to_owned(), "nope".to_owned(), false);
+        assert!(matches!(result, Err(BranchError::InvalidRevision)));
+        let result = create_branch(path.clone(), "a..b".to_owned(), "HEAD".to_owned(), false);
+        assert!(matches!(result, Err(BranchError::InvalidName)));
+        let result = create_branch(path, "main".to_owned(), "HEAD".to_owned(), false);
+        assert!(matches!(result, Err(BranchError::AlreadyExists)));
+    }
+
+    #[test]
+    fn renames_the_checked_out_branch() {
+        let (_dir, repo, path) = setup();
+        let tip = head(&repo);
+
+        rename_branch(path.clone(), "main".to_owned(), "trunk".to_owned())
+            .ok()
+            .unwrap();
+
+        assert_eq!(branch_tip(&repo, "main"), None);
+        assert_eq!(branch_tip(&repo, "trunk"), Some(tip));
+        assert_eq!(repo.head().unwrap().shorthand(), Some("trunk"));
+        let result = rename_branch(path, "main".to_owned(), "other".to_owned());
+        assert!(matches!(result, Err(BranchError::NotFound)));
+    }
+
+    #[test]
+    fn deletes_only_merged_branches() {
+        let (_dir, repo, path) = setup();
+        let base = head(&repo);
+        repo.branch("merged", &repo.find_commit(base).unwrap(), false)
+            .unwrap();
+        commit(&repo, Some("refs/heads/unmerged"), &[base]);
+
+        delete_branch(path.clone(), "merged".to_owned(), false)
+            .ok()
+            .unwrap();
+        assert_eq!(branch_tip(&repo, "merged"), None);
+
+        let result = delete_branch(path.clone(), "unmerged".to_owned(), false);
+        assert!(matches!(result, Err(BranchError::NotFullyMerged)));
+        delete_branch(path.clone(), "unmerged".to_owned(), true)
+            .ok()
+            .unwrap();
+        assert_eq!(branch_tip(&repo, "unmerged"), None);
+
+        let result = delete_branch(path, "main".to_owned(), true);
+        assert!(matches!(result, Err(BranchError::IsHead)));
+    }
+
+    #[test]
+    fn checks_merged_against_the_upstream() {
+        let (_dir, repo, path) = setup();
+        let base = head(&repo);
+        repo.remote("origin", "https://example.com/repo.git")
+            .unwrap();
+        // Pushed, but not merged into HEAD
+        let pushed = commit(&repo, Some("refs/heads/pushed"), &[base]);
+        repo.reference("refs/remotes/origin/pushed", pushed, false, "")
+            .unwrap();
+
+        set_upstream(
+            path.clone(),
+            "pushed".to_owned(),
+            Some("origin/pushed".to_owned()),
+        )
+        .ok()
+        .unwrap();
+        let branch = repo.find_branch("pushed", BranchType::Local).unwrap();
+        assert_eq!(
+            branch.upstream().unwrap().name().unwrap(),
+            Some("origin/pushed")
+        );
+
+        delete_branch(path, "pushed".to_owned(), false)
+            .ok()
+            .unwrap();
+        assert_eq!(branch_tip(&repo, "pushed"), None);
+    }
+
+    #[test]
+    fn unsets_the_upstream() {
+        let (_dir, repo, path) = setup();
+        repo.remote("origin", "https://example.com/repo.git")
+            .unwrap();
+        repo.reference("refs/remotes/origin/main", head(&repo), false, "")
+            .unwrap();
+        set_upstream(
+            path.clone(),
+            "main".to_owned(),
+            Some("origin/main".to_owned()),
+        )
+        .ok()
+        .unwrap();
+
+        set_upstream(path, "main".to_owned(), None).ok().unwrap();
+
+        let branch = repo.find_branch("main", BranchType::Local).unwrap();
+        assert!(branch.upstream().is_err());
+    }
+}
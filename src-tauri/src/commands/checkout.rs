@@ -1,13 +1,14 @@
-use super::serializer::git_error::GitError;
-use git2::{build::CheckoutBuilder, Oid, Repository};
+use super::{find_commit, serializer::git_error::GitError};
+use git2::{build::CheckoutBuilder, Reference, Repository};
 use logging_timer::time;
 
+/// Detaches HEAD at `id`, which can be any revspec that points to a commit.
 #[time]
 #[tauri::command(async)]
 pub fn checkout_commit(path: String, id: String) -> Result<(), GitError> {
     let repo = Repository::open(path)?;
 
-    let commit = repo.find_commit(Oid::from_str(&id)?)?;
+    let commit = find_commit(&repo, &id)?;
     let tree = commit.tree()?;
 
     let mut opts = CheckoutBuilder::new();
@@ -38,12 +39,17 @@ pub fn checkout_local(path: String, branch_name: String) -> Result<(), GitError>
         })
         .ok_or(GitError::Wrapped("Can't find branch".to_owned()))?;
 
-    let reference = branch.into_reference();
+    checkout_reference(&repo, &branch.into_reference())?;
+
+    Ok(())
+}
 
+/// Moves HEAD to a local branch, updating the working dir if it can be done safely.
+pub fn checkout_reference(repo: &Repository, reference: &Reference) -> Result<(), git2::Error> {
     let tree = reference.peel_to_tree()?;
-    let name = reference.name().ok_or(GitError::Wrapped(
-        "Reference doesn't have a name".to_owned(),
-    ))?;
+    let name = reference
+        .name()
+        .ok_or(git2::Error::from_str("Reference doesn't have a name"))?;
 
     let mut opts = CheckoutBuilder::new();
     opts.safe();
@@ -1,104 +1,273 @@
-use std::{env, thread};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use git2::{AutotagOption, Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository};
+use git2::{AutotagOption, FetchOptions, Oid, RemoteCallbacks, RemoteUpdateFlags, Repository};
 use itertools::Itertools;
 use log::{error, info};
 use logging_timer::{executing, timer};
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
 
-use super::serializer::git_error::GitError;
+use super::{get_remote_callbacks, serializer::git_error::GitError, HostKeyError};
+use crate::AppState;
 
+// Transfer progress gets called for every object, so it's only emitted this often.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Serialize)]
+pub struct FetchProgress {
+    pub remote: String,
+    pub progress: FetchStage,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum FetchStage {
+    Transfer {
+        received_objects: usize,
+        indexed_objects: usize,
+        total_objects: usize,
+        received_bytes: usize,
+    },
+    // Messages from the remote, e.g. "Counting objects: 10% (1/10)"
+    Sideband(String),
+    // A missing id means the ref got created (`old`) or deleted (`new`)
+    UpdatedRef {
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    // With the error if it failed or got cancelled
+    Done(Option<FetchError>),
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum FetchError {
+    Read(String),
+    Cancelled,
+    HostKey(HostKeyError),
+}
+
+impl From<git2::Error> for FetchError {
+    fn from(value: git2::Error) -> Self {
+        match HostKeyError::from_error(&value) {
+            Some(error) => FetchError::HostKey(error),
+            None => FetchError::Read(value.message().to_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct FetchRequest {
+    // Only this remote instead of all of them
+    #[serde(default)]
+    pub remote: Option<String>,
+    // Instead of the ones configured for the remote, usually along with `remote`
+    #[serde(default)]
+    pub refspecs: Vec<String>,
+    // `None` follows `remote.<name>.prune`, then `fetch.prune`
+    #[serde(default)]
+    pub prune: Option<bool>,
+    #[serde(default)]
+    pub tags: FetchTags,
+    // Only this many commits from the tip of each ref, like `git fetch --depth`.
+    // The local transport ignores it and fetches everything.
+    #[serde(default)]
+    pub depth: Option<u32>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub enum FetchTags {
+    // Tags pointing to what gets fetched, or what `remote.<name>.tagOpt` says
+    #[default]
+    Auto,
+    All,
+    None,
+}
+
+impl FetchTags {
+    fn autotag(&self) -> AutotagOption {
+        match self {
+            FetchTags::Auto => AutotagOption::Unspecified,
+            FetchTags::All => AutotagOption::All,
+            FetchTags::None => AutotagOption::None,
+        }
+    }
+}
+
+/// What changed on a remote, by full ref name.
+#[derive(Default, Serialize)]
+pub struct FetchReport {
+    pub remote: String,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub pruned: Vec<String>,
+    pub error: Option<FetchError>,
+}
+
+/// Fetches every remote (or the one in `request`) in parallel, emitting their progress to
+/// `fetch-progress` as `FetchProgress`. It can be stopped through `cancel_fetch`.
 #[tauri::command(async)]
-pub fn fetch(path: String) -> Result<(), GitError> {
+pub fn fetch(
+    path: String,
+    request: Option<FetchRequest>,
+    window: Window,
+    state: State<AppState>,
+) -> Result<Vec<FetchReport>, GitError> {
     let tmr = timer!("fetch()");
     let repo = Repository::open(path.clone())?;
-    let remotes = get_remotes(&repo);
+    let request = request.unwrap_or_default();
+    let remotes = match &request.remote {
+        Some(remote) => vec![remote.clone()],
+        None => get_remotes(&repo),
+    };
 
     executing!(tmr, "get remotes");
 
-    remotes
+    let cancelled = register_fetch(&state, &path);
+
+    let reports = remotes
         .into_iter()
         .map(|remote_name| {
             let path = path.clone();
+            let request = request.clone();
+            let window = window.clone();
+            let cancelled = cancelled.clone();
             thread::spawn(move || {
-                if let Err(err) = fetch_remote(path, &remote_name) {
+                let result = fetch_remote(path, &remote_name, &request, Some(&window), &cancelled);
+                if let Err(err) = &result {
                     error!("Error fetching remote {}: {:?}", remote_name, err);
                 }
+                let progress = FetchProgress {
+                    remote: remote_name.clone(),
+                    progress: FetchStage::Done(result.as_ref().err().cloned()),
+                };
+                window.emit("fetch-progress", progress).ok();
+
+                result.unwrap_or_else(|error| FetchReport {
+                    remote: remote_name,
+                    error: Some(error),
+                    ..Default::default()
+                })
             })
         })
-        .for_each(|handle| {
-            handle.join().ok();
-        });
+        .collect_vec()
+        .into_iter()
+        .filter_map(|handle| handle.join().ok())
+        .collect();
+
+    unregister_fetch(&state, &path, &cancelled);
 
-    Ok(())
+    Ok(reports)
 }
 
-fn fetch_remote(path: String, remote_name: &str) -> Result<(), GitError> {
+/// Stops the fetch running on `path`, every remote fails with a cancelled error.
+#[tauri::command]
+pub fn cancel_fetch(path: String, state: State<AppState>) {
+    state
+        .fetches
+        .lock()
+        .and_then(|fetches| {
+            if let Some(cancelled) = fetches.get(&path) {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        })
+        .ok();
+}
+
+pub fn register_fetch(state: &AppState, path: &str) -> Arc<AtomicBool> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .fetches
+        .lock()
+        .and_then(|mut fetches| {
+            fetches.insert(path.to_owned(), cancelled.clone());
+            Ok(())
+        })
+        .ok();
+    cancelled
+}
+
+// Only if it's still this fetch, another one could have started on the same repo.
+pub fn unregister_fetch(state: &AppState, path: &str, cancelled: &Arc<AtomicBool>) {
+    state
+        .fetches
+        .lock()
+        .and_then(|mut fetches| {
+            if fetches
+                .get(path)
+                .map_or(false, |current| Arc::ptr_eq(current, cancelled))
+            {
+                fetches.remove(path);
+            }
+            Ok(())
+        })
+        .ok();
+}
+
+/// Fetches the refs of `remote_name` and updates its remote-tracking refs.
+/// The progress goes to `window` if there's one, and it stops as soon as `cancelled` is set.
+pub fn fetch_remote(
+    path: String,
+    remote_name: &str,
+    request: &FetchRequest,
+    window: Option<&Window>,
+    cancelled: &AtomicBool,
+) -> Result<FetchReport, FetchError> {
     let repo = Repository::open(path)?;
     let mut remote = repo.find_remote(remote_name)?;
-    let mut cb = RemoteCallbacks::new();
-    cb.credentials(|_url, username_from_url, allowed_types| {
-        if allowed_types.contains(CredentialType::SSH_KEY) {
-            if let Some(username) = username_from_url {
-                Cred::ssh_key(
-                    username,
-                    None,
-                    std::path::Path::new(&format!(
-                        "{}/.ssh/id_rsa",
-                        env::var("HOME").expect("Needs home env var")
-                    )),
-                    None,
-                )?;
-            } else {
-                Err(git2::Error::new(
-                    git2::ErrorCode::User,
-                    git2::ErrorClass::None,
-                    "SSH_KEY credential needs username on url",
-                ))?
-            }
-            // } else if _allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
-            //     let mut backend = dialog::backends::Dialog::new();
-            //     backend.set_backtitle("dialog demo");
-            //     backend.set_width(100);
-            //     backend.set_height(10);
-
-            //     let username = dialog::Input::new("Username")
-            //         .title("Git Credentials")
-            //         .show_with(&backend)
-            //         .expect("Could not display dialog box")
-            //         .unwrap();
-
-            //     let password = dialog::Password::new("Password")
-            //         .title("Git Credentials")
-            //         .show_with(&backend)
-            //         .expect("Could not display dialog box")
-            //         .unwrap();
-
-            //     Cred::userpass_plaintext(&username, &password)
-            // } else {
-            //     panic!(
-            //         "Credentials requested {} {:?} {:?}",
-            //         _url, username_from_url, _allowed_types
-            //     );
-        }
-        // println!(
-        //     "Unsupported credentials for remote {:?}: {:?}",
-        //     name, allowed_types
-        // );
-
-        Err(git2::Error::new(
-            git2::ErrorCode::User,
-            git2::ErrorClass::None,
-            "Unsupported credentials",
-        ))
+    let config = repo.config()?;
+    let prune = request.prune.unwrap_or_else(|| {
+        config
+            .get_bool(&format!("remote.{}.prune", remote_name))
+            .or_else(|_| config.get_bool("fetch.prune"))
+            .unwrap_or(false)
     });
+    let emit = |progress: FetchStage| {
+        if let Some(window) = window {
+            let progress = FetchProgress {
+                remote: remote_name.to_owned(),
+                progress,
+            };
+            window.emit("fetch-progress", progress).ok();
+        }
+    };
+
+    let (mut cb, used_credentials) = get_remote_callbacks(&repo, &remote, window);
+    let mut last_emitted: Option<Instant> = None;
     cb.transfer_progress(|progress| {
-        info!(
-            "Transfer progress {} {}/{}",
-            progress.received_bytes(),
-            progress.received_objects(),
-            progress.total_objects()
-        );
-        true
+        let done = progress.indexed_objects() == progress.total_objects();
+        if done || last_emitted.map_or(true, |last| last.elapsed() >= PROGRESS_INTERVAL) {
+            last_emitted = Some(Instant::now());
+            info!(
+                "Transfer progress {} {}/{}",
+                progress.received_bytes(),
+                progress.received_objects(),
+                progress.total_objects()
+            );
+            emit(FetchStage::Transfer {
+                received_objects: progress.received_objects(),
+                indexed_objects: progress.indexed_objects(),
+                total_objects: progress.total_objects(),
+                received_bytes: progress.received_bytes(),
+            });
+        }
+        !cancelled.load(Ordering::Relaxed)
+    });
+    cb.sideband_progress(|message| {
+        emit(FetchStage::Sideband(
+            String::from_utf8_lossy(message).trim_end().to_owned(),
+        ));
+        !cancelled.load(Ordering::Relaxed)
     });
 
     // From https://github.com/rust-lang/git2-rs/blob/master/examples/fetch.rs
@@ -107,8 +276,20 @@ fn fetch_remote(path: String, remote_name: &str) -> Result<(), GitError> {
     // received data and the indexer stats which lets you inform the user about
     // progress.
     let mut options = FetchOptions::new();
-    options.remote_callbacks(cb);
-    remote.download(&[] as &[&str], Some(&mut options))?;
+    options
+        .remote_callbacks(cb)
+        .download_tags(request.tags.autotag());
+    if let Some(depth) = request.depth {
+        options.depth(depth.try_into().unwrap_or(i32::MAX));
+    }
+    let downloaded = remote.download(&request.refspecs, Some(&mut options));
+    used_credentials.settle(&downloaded);
+    // Some transports don't stop on their own, so refs don't get updated to a partial pack.
+    if cancelled.load(Ordering::Relaxed) {
+        remote.disconnect().ok();
+        return Err(FetchError::Cancelled);
+    }
+    downloaded?;
 
     // Disconnect the underlying connection to prevent from idling.
     remote.disconnect()?;
@@ -117,9 +298,48 @@ fn fetch_remote(path: String, remote_name: &str) -> Result<(), GitError> {
     // commits. This may be needed even if there was no packfile to download,
     // which can happen e.g. when the branches have been changed but all the
     // needed objects are available locally.
-    remote.update_tips(None, true, AutotagOption::Unspecified, None)?;
+    let mut report = FetchReport {
+        remote: remote_name.to_owned(),
+        ..Default::default()
+    };
+    // libgit2 doesn't always give a zero id for the refs it creates (e.g. the remote-tracking
+    // refs it also updates when fetching a refspec), so it's checked against what was there.
+    let existing: HashSet<String> = repo
+        .references()?
+        .names()
+        .filter_map(|name| name.ok().map(|name| name.to_owned()))
+        .collect();
+    let mut cb = RemoteCallbacks::new();
+    cb.update_tips(|name, old, new| {
+        let created = old.is_zero() || !existing.contains(name);
+        match (created, new.is_zero()) {
+            (true, _) => report.created.push(name.to_owned()),
+            (_, true) => report.pruned.push(name.to_owned()),
+            _ => report.updated.push(name.to_owned()),
+        }
+        let to_string = |oid: Oid| (!oid.is_zero()).then(|| oid.to_string());
+        emit(FetchStage::UpdatedRef {
+            name: name.to_owned(),
+            old: if created { None } else { to_string(old) },
+            new: to_string(new),
+        });
+        true
+    });
+    remote.update_tips(
+        Some(&mut cb),
+        RemoteUpdateFlags::UPDATE_FETCHHEAD,
+        request.tags.autotag(),
+        None,
+    )?;
+
+    // Remote-tracking refs of branches that are gone from the remote
+    if prune {
+        remote.prune(Some(cb))?;
+    } else {
+        drop(cb);
+    }
 
-    Ok(())
+    Ok(report)
 }
 
 fn get_remotes(repo: &Repository) -> Vec<String> {
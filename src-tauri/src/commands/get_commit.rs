@@ -1,24 +1,29 @@
 use crate::commands::serializer::delta::Delta;
-use git2::{Oid, Repository};
+use crate::signature::{verify_commit, SignatureStatus};
+use git2::Repository;
 use log::error;
 use logging_timer::time;
 use serde::Serialize;
 
-use super::serializer::git_error::GitError;
+use super::{find_commit, serializer::git_error::GitError};
 
 #[derive(Serialize)]
 pub struct CommitContents {
     insertions: usize,
     deletions: usize,
     deltas: Vec<Delta>,
+    signature: SignatureStatus,
+    // From `refs/notes/commits`, or whatever `core.notesRef` says
+    note: Option<String>,
 }
 
+/// `id` can be any revspec that points to a commit.
 #[time]
 #[tauri::command(async)]
 pub fn get_commit(path: String, id: String) -> Result<CommitContents, GitError> {
     let repo = Repository::open(path)?;
 
-    let commit = repo.find_commit(Oid::from_str(&id)?)?;
+    let commit = find_commit(&repo, &id)?;
     let commit_tree = commit.tree()?;
     let parent_tree = commit
         .parents()
@@ -31,6 +36,11 @@ pub fn get_commit(path: String, id: String) -> Result<CommitContents, GitError>
     Ok(CommitContents {
         insertions: stats.insertions(),
         deletions: stats.deletions(),
+        signature: verify_commit(&repo, &commit),
+        note: repo
+            .find_note(None, commit.id())
+            .ok()
+            .and_then(|note| note.message().map(|v| v.to_owned())),
         deltas: diff
             .deltas()
             .filter_map(|d| {
@@ -1,32 +1,238 @@
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
 
-use crate::positioned_commit::get_positioned_commits;
+use crate::graph_cache::{load_graph, save_graph, GraphKey};
+use crate::positioned_commit::{get_positioned_commits, HistoryOptions, PositionedCommit};
+use crate::AppState;
 use git2::Repository;
+use itertools::Itertools;
 use logging_timer::time;
-use tauri::Window;
+use serde::Serialize;
+use tauri::{State, Window};
 
 use super::serializer::git_error::GitError;
 
+const BATCH_SIZE: usize = 200;
+// Batches emitted before waiting for the frontend to acknowledge one of them.
+const MAX_BATCHES_IN_FLIGHT: usize = 2;
+// If the frontend stops acknowledging (e.g. the window reloaded) the walk gives up.
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum CommitStreamSignal {
+    Ack,
+    Cancel,
+}
+
+pub type CommitStreams = HashMap<String, Sender<CommitStreamSignal>>;
+
+#[derive(Serialize)]
+pub struct StreamedGraph {
+    pub batches: usize,
+    // What the graph was positioned for, to be given to `update_commits` when refs move.
+    // `None` if the stream was cancelled.
+    pub tips: Option<Vec<String>>,
+}
+
+/// Streams the positioned commits in batches to `get_commits-stream-{correlation_id}`.
+/// Every batch has to be acknowledged through `ack_commits`, and the stream can be
+/// stopped at any point through `cancel_commits`.
+/// The graph is served from the cache when none of the ref tips has moved.
 #[time]
 #[tauri::command(async)]
 pub fn get_commits(
     path: String,
     correlation_id: String,
+    options: Option<HistoryOptions>,
     window: Window,
-) -> Result<usize, GitError> {
+    state: State<AppState>,
+    app: tauri::AppHandle,
+) -> Result<StreamedGraph, GitError> {
+    // Registered before anything else, so a cancel sent right away isn't missed.
+    let receiver = register_commit_stream(&state, &correlation_id);
+    let response_channel = format!("get_commits-stream-{correlation_id}");
+
+    let result = stream_graph(&path, options, &window, &response_channel, &receiver, &app);
+
+    unregister_commit_stream(&state, &correlation_id);
+
+    result
+}
+
+fn stream_graph(
+    path: &str,
+    options: Option<HistoryOptions>,
+    window: &Window,
+    response_channel: &str,
+    receiver: &Receiver<CommitStreamSignal>,
+    app: &tauri::AppHandle,
+) -> Result<StreamedGraph, GitError> {
     let repo = Repository::open(path)?;
+    let options = options.unwrap_or_default();
+    let key = GraphKey::new(&repo, &options)?;
 
-    let response_channel = format!("get_commits-stream-{correlation_id}");
-    let result = get_positioned_commits(&repo)?
-        .enumerate()
-        .map(|(i, x)| {
-            window.emit(&response_channel, &x).ok();
-            if i % 50 == 0 {
-                std::thread::sleep(Duration::from_millis(20));
+    let tips = key.tips.clone();
+
+    let (batches, completed) = match load_graph(app, path, &options, &key) {
+        Some(commits) => {
+            let (batches, commits) =
+                emit_commits(commits.into_iter(), window, response_channel, receiver)?;
+            (batches, commits.is_some())
+        }
+        None => {
+            let commits = get_positioned_commits(&repo, &options)?;
+            let (batches, commits) = emit_commits(commits, window, response_channel, receiver)?;
+            let completed = commits.is_some();
+            if let Some(commits) = commits {
+                save_graph(app, path, &options, key, commits);
             }
-            x
+            (batches, completed)
+        }
+    };
+
+    Ok(StreamedGraph {
+        batches,
+        tips: completed.then_some(tips),
+    })
+}
+
+/// Makes the stream under `correlation_id` reachable from `ack_commits` and `cancel_commits`.
+pub fn register_commit_stream(
+    state: &AppState,
+    correlation_id: &str,
+) -> Receiver<CommitStreamSignal> {
+    let (sender, receiver) = channel();
+    state
+        .commit_streams
+        .lock()
+        .and_then(|mut streams| {
+            streams.insert(correlation_id.to_owned(), sender);
+            Ok(())
         })
-        .count();
+        .ok();
+    receiver
+}
+
+pub fn unregister_commit_stream(state: &AppState, correlation_id: &str) {
+    state
+        .commit_streams
+        .lock()
+        .and_then(|mut streams| {
+            streams.remove(correlation_id);
+            Ok(())
+        })
+        .ok();
+}
+
+/// Emits batches to a stream registered through `register_commit_stream`, waiting
+/// for the frontend to acknowledge them when it falls behind.
+pub struct BatchEmitter<'a> {
+    window: &'a Window,
+    response_channel: &'a str,
+    receiver: &'a Receiver<CommitStreamSignal>,
+    in_flight: usize,
+    emitted: usize,
+}
 
-    Ok(result)
+impl<'a> BatchEmitter<'a> {
+    pub fn new(
+        window: &'a Window,
+        response_channel: &'a str,
+        receiver: &'a Receiver<CommitStreamSignal>,
+    ) -> Self {
+        BatchEmitter {
+            window,
+            response_channel,
+            receiver,
+            in_flight: 0,
+            emitted: 0,
+        }
+    }
+
+    /// Amount of batches emitted so far.
+    pub fn emitted(&self) -> usize {
+        self.emitted
+    }
+
+    /// Takes the signals received so far without blocking.
+    pub fn is_cancelled(&mut self) -> bool {
+        while let Ok(signal) = self.receiver.try_recv() {
+            match signal {
+                CommitStreamSignal::Ack => self.in_flight = self.in_flight.saturating_sub(1),
+                CommitStreamSignal::Cancel => return true,
+            }
+        }
+        false
+    }
+
+    /// Returns false if the stream was cancelled, in which case `batch` isn't emitted.
+    pub fn emit<T: Serialize>(&mut self, batch: &[T]) -> bool {
+        if self.is_cancelled() {
+            return false;
+        }
+        // Block while the frontend is falling behind.
+        while self.in_flight >= MAX_BATCHES_IN_FLIGHT {
+            match self
+                .receiver
+                .recv_timeout(ACK_TIMEOUT)
+                .unwrap_or(CommitStreamSignal::Cancel)
+            {
+                CommitStreamSignal::Ack => self.in_flight -= 1,
+                CommitStreamSignal::Cancel => return false,
+            }
+        }
+
+        self.window.emit(self.response_channel, batch).ok();
+        self.emitted += 1;
+        self.in_flight += 1;
+        true
+    }
+}
+
+/// Returns the amount of batches emitted, and every commit if the stream wasn't cancelled.
+fn emit_commits(
+    commits: impl Iterator<Item = PositionedCommit>,
+    window: &Window,
+    response_channel: &str,
+    receiver: &Receiver<CommitStreamSignal>,
+) -> Result<(usize, Option<Vec<PositionedCommit>>), GitError> {
+    let mut emitter = BatchEmitter::new(window, response_channel, receiver);
+    let mut result = vec![];
+
+    for batch in &commits.chunks(BATCH_SIZE) {
+        let batch = batch.collect_vec();
+        if !emitter.emit(&batch) {
+            return Ok((emitter.emitted(), None));
+        }
+        result.extend(batch);
+    }
+
+    Ok((emitter.emitted(), Some(result)))
+}
+
+#[time]
+#[tauri::command(async)]
+pub fn ack_commits(correlation_id: String, state: State<AppState>) {
+    send_commit_stream_signal(&state, &correlation_id, CommitStreamSignal::Ack);
+}
+
+#[time]
+#[tauri::command(async)]
+pub fn cancel_commits(correlation_id: String, state: State<AppState>) {
+    send_commit_stream_signal(&state, &correlation_id, CommitStreamSignal::Cancel);
+}
+
+fn send_commit_stream_signal(state: &AppState, correlation_id: &str, signal: CommitStreamSignal) {
+    state
+        .commit_streams
+        .lock()
+        .and_then(|streams| {
+            if let Some(sender) = streams.get(correlation_id) {
+                sender.send(signal).ok();
+            }
+            Ok(())
+        })
+        .ok();
 }
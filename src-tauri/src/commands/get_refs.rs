@@ -1,4 +1,6 @@
-use git2::{Branch, BranchType, Repository};
+use crate::positioned_commit::SignatureInfo;
+use crate::signature::{get_tag_message, get_tag_signature_status, SignatureStatus};
+use git2::{Branch, BranchType, ErrorCode, Repository};
 use itertools::Itertools;
 use logging_timer::time;
 use serde::Serialize;
@@ -10,6 +12,19 @@ pub struct LocalRef {
     id: String,
     name: String,
     is_head: bool,
+    upstream: Option<UpstreamInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamInfo {
+    // e.g. `origin/main`
+    name: String,
+    // It's configured, but the remote branch doesn't exist anymore (e.g. pruned after a fetch)
+    gone: bool,
+    // Commits only on the local branch, and only on the upstream.
+    // `None` when they couldn't be counted, or the upstream is gone.
+    ahead: Option<usize>,
+    behind: Option<usize>,
 }
 
 pub enum ParseRefError {
@@ -35,6 +50,7 @@ impl TryFrom<Branch<'_>> for LocalRef {
                 .to_string(),
             name: branch.name()?.ok_or(ParseRefError::InvalidUTF8)?.to_owned(),
             is_head: branch.is_head(),
+            upstream: None,
         })
     }
 }
@@ -70,13 +86,47 @@ impl TryFrom<Branch<'_>> for RemoteRef {
     }
 }
 
+// Default branch of a remote, `origin/HEAD -> origin/main`
+#[derive(Debug, Serialize)]
+pub struct RemoteHeadRef {
+    id: String,
+    remote: String,
+    // Branch of the remote it points to, e.g. `main`
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagRef {
+    // Commit it points to, annotated tags are peeled
+    id: String,
+    name: String,
+    annotated: bool,
+    // Only for annotated tags
+    tagger: Option<SignatureInfo>,
+    message: Option<String>,
+    // Only whether it's signed, `verify_tag` checks it
+    signature: SignatureStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StashRef {
+    id: String,
+    index: usize,
+    message: String,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", content = "payload")]
 pub enum Ref {
     Head(String),
+    // HEAD is on a branch without commits (empty repo or orphan branch), which will get
+    // created with the first commit.
+    UnbornHead(String),
     LocalBranch(LocalRef),
     RemoteBranch(RemoteRef),
-    Tag(LocalRef),
+    RemoteHead(RemoteHeadRef),
+    Tag(TagRef),
+    Stash(StashRef),
 }
 
 impl TryFrom<(Branch<'_>, BranchType)> for Ref {
@@ -95,13 +145,17 @@ impl TryFrom<(Branch<'_>, BranchType)> for Ref {
 pub fn get_refs(path: String) -> Result<Vec<Ref>, GitError> {
     let repo = Repository::open(path)?;
 
-    // TODO get orphan branch
-    let head_option = repo
-        .head()
-        .and_then(|head| head.resolve())
-        .ok()
-        .and_then(|reference| reference.target())
-        .map(|oid| Ref::Head(oid.to_string()));
+    let head_option = match repo.head() {
+        Ok(head) => head
+            .resolve()
+            .ok()
+            .and_then(|reference| reference.target())
+            .map(|oid| Ref::Head(oid.to_string())),
+        Err(e) if e.code() == ErrorCode::UnbornBranch => {
+            get_unborn_branch(&repo).map(Ref::UnbornHead)
+        }
+        Err(_) => None,
+    };
 
     let mut list = if let Some(head) = head_option {
         vec![head]
@@ -109,40 +163,142 @@ pub fn get_refs(path: String) -> Result<Vec<Ref>, GitError> {
         vec![]
     };
 
-    let branches = repo
-        .branches(None)?
-        .filter_map(|result| result.ok().and_then(|branch| Ref::try_from(branch).ok()));
+    let branches = repo.branches(None)?.filter_map(|result| {
+        result.ok().and_then(|(branch, branch_type)| {
+            let upstream = get_upstream(&repo, &branch);
+            Ref::try_from((branch, branch_type))
+                .ok()
+                .map(|reference| match reference {
+                    Ref::LocalBranch(local) => Ref::LocalBranch(LocalRef { upstream, ..local }),
+                    reference => reference,
+                })
+        })
+    });
 
     list.extend(branches);
 
-    let tags = get_tags(&repo)?;
-    let tags = tags.iter().map(|(oid, name)| {
-        Ref::Tag(LocalRef {
-            id: oid.to_string(),
-            name: name.clone(),
-            is_head: false,
-        })
-    });
+    list.extend(get_remote_heads(&repo)?.into_iter().map(Ref::RemoteHead));
+
+    list.extend(get_tags(&repo)?.into_iter().map(Ref::Tag));
 
-    list.extend(tags);
+    list.extend(get_stashes(&repo)?.into_iter().map(Ref::Stash));
 
     Ok(list)
 }
 
-fn get_tags(repo: &Repository) -> Result<Vec<(git2::Oid, String)>, git2::Error> {
-    let mut tags = vec![];
+fn get_unborn_branch(repo: &Repository) -> Option<String> {
+    repo.find_reference("HEAD")
+        .ok()?
+        .symbolic_target()?
+        .strip_prefix("refs/heads/")
+        .map(|name| name.to_owned())
+}
+
+fn get_remote_heads(repo: &Repository) -> Result<Vec<RemoteHeadRef>, git2::Error> {
+    Ok(repo
+        .remotes()?
+        .iter()
+        .flatten()
+        .filter_map(|remote| {
+            let prefix = format!("refs/remotes/{remote}/");
+            let reference = repo.find_reference(&format!("{prefix}HEAD")).ok()?;
+            let name = reference
+                .symbolic_target()?
+                .strip_prefix(&prefix)?
+                .to_owned();
+            let id = reference.resolve().ok()?.target()?.to_string();
 
-    repo.tag_foreach(|id, u8| {
-        let ref_name = String::from_utf8_lossy(u8).to_string();
-        let split = ref_name
-            .splitn(3, "/")
-            .collect_tuple()
-            .map(|(_, _, name)| name.to_owned());
-        if let Some(name) = split {
-            tags.push((id, name));
-        }
-        true
-    })?;
+            Some(RemoteHeadRef {
+                id,
+                remote: remote.to_owned(),
+                name,
+            })
+        })
+        .collect())
+}
+
+fn get_upstream(repo: &Repository, branch: &Branch) -> Option<UpstreamInfo> {
+    let reference = branch.get();
+    if !reference.is_branch() {
+        return None;
+    }
+    // Only reads the config, so it's there even if the remote branch is gone
+    let upstream_name = repo.branch_upstream_name(reference.name()?).ok()?;
+    let upstream_name = upstream_name.as_str()?;
+    let name = upstream_name
+        .strip_prefix("refs/remotes/")
+        .or_else(|| upstream_name.strip_prefix("refs/heads/"))
+        .unwrap_or(upstream_name)
+        .to_owned();
+
+    let upstream_id = repo
+        .find_reference(upstream_name)
+        .ok()
+        .and_then(|upstream| upstream.target());
+    let counts = match (reference.target(), upstream_id) {
+        (Some(local), Some(upstream)) => repo.graph_ahead_behind(local, upstream).ok(),
+        _ => None,
+    };
+
+    Some(UpstreamInfo {
+        name,
+        gone: upstream_id.is_none(),
+        ahead: counts.map(|(ahead, _)| ahead),
+        behind: counts.map(|(_, behind)| behind),
+    })
+}
+
+fn get_stashes(repo: &Repository) -> Result<Vec<StashRef>, git2::Error> {
+    if repo.find_reference("refs/stash").is_err() {
+        return Ok(vec![]);
+    }
+
+    Ok(repo
+        .reflog("refs/stash")?
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| StashRef {
+            id: entry.id_new().to_string(),
+            index,
+            message: entry.message().unwrap_or("").to_owned(),
+        })
+        .collect())
+}
+
+fn get_tags(repo: &Repository) -> Result<Vec<TagRef>, git2::Error> {
+    let mailmap = repo.mailmap()?;
 
-    Ok(tags)
+    Ok(repo
+        .references_glob("refs/tags/*")?
+        .filter_map(|reference| reference.ok())
+        .filter_map(|reference| {
+            let name = reference.shorthand()?.to_owned();
+            // Tags of trees or blobs can't be shown on the graph
+            let id = reference.peel_to_commit().ok()?.id().to_string();
+
+            let tag = reference
+                .target()
+                .and_then(|target| repo.find_tag(target).ok());
+            Some(match tag {
+                Some(tag) => TagRef {
+                    id,
+                    name,
+                    annotated: true,
+                    tagger: tag.tagger().map(|tagger| {
+                        SignatureInfo::new(&mailmap.resolve_signature(&tagger).unwrap_or(tagger))
+                    }),
+                    message: get_tag_message(&tag),
+                    signature: get_tag_signature_status(&tag),
+                },
+                None => TagRef {
+                    id,
+                    name,
+                    annotated: false,
+                    tagger: None,
+                    message: None,
+                    signature: SignatureStatus::Unsigned,
+                },
+            })
+        })
+        .collect())
 }
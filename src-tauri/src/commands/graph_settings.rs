@@ -0,0 +1,21 @@
+use crate::{
+    positioned_commit::HistoryLayout,
+    settings::{GraphSettings, JsonSettingsLoader},
+};
+use logging_timer::time;
+
+#[time]
+#[tauri::command(async)]
+pub fn get_graph_layout(path: String, app: tauri::AppHandle) -> HistoryLayout {
+    GraphSettings::load(&app)
+        .and_then(|mut settings| settings.layouts.remove(&path))
+        .unwrap_or_default()
+}
+
+#[time]
+#[tauri::command(async)]
+pub fn set_graph_layout(path: String, layout: HistoryLayout, app: tauri::AppHandle) {
+    let mut settings = GraphSettings::load(&app).unwrap_or_default();
+    settings.layouts.insert(path, layout);
+    settings.save(&app)
+}
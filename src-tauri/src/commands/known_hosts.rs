@@ -0,0 +1,405 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use git2::{
+    cert::{Cert, SshHostKeyType},
+    CertificateCheckStatus, ErrorClass, ErrorCode,
+};
+use serde::{Deserialize, Serialize};
+
+use super::wildcard_matches;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HostKey {
+    // As it goes in known_hosts, `[host]:port` if it's not on 22
+    pub host: String,
+    pub key_type: String,
+    // SHA256:..., same as ssh shows it
+    pub fingerprint: String,
+}
+
+/// Why the connection to the remote was refused.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum HostKeyError {
+    // Doesn't match the one in known_hosts, someone could be impersonating the host
+    Changed(HostKey),
+    // Not in known_hosts, and the user didn't trust it (or couldn't be asked)
+    Untrusted(HostKey),
+    // Marked as `@revoked` in known_hosts
+    Revoked(HostKey),
+    // The ssh library didn't give a key that can be checked, for this host
+    Unsupported(String),
+    // known_hosts couldn't be read, so it's not known whether the key is trusted
+    Lookup(String),
+}
+
+impl HostKeyError {
+    /// The error the callback gives back to libgit2 carries it, so it can be recovered
+    /// from the error of the remote operation.
+    pub fn from_error(error: &git2::Error) -> Option<Self> {
+        if error.code() != ErrorCode::Certificate || error.class() != ErrorClass::Ssh {
+            return None;
+        }
+        serde_json::from_str(error.message()).ok()
+    }
+
+    fn into_error(self) -> git2::Error {
+        git2::Error::new(
+            ErrorCode::Certificate,
+            ErrorClass::Ssh,
+            serde_json::to_string(&self).unwrap_or_default(),
+        )
+    }
+}
+
+enum HostKeyStatus {
+    Known,
+    Unknown(HostKey),
+    Changed(HostKey),
+    Revoked(HostKey),
+}
+
+/// Checks the host key against known_hosts, same as ssh does. `trust` gets asked
+/// whether to add an unknown one. Certificates other than ssh's (https) are left to libgit2.
+pub fn check_host_key(
+    cert: &Cert,
+    host: &str,
+    port: Option<u16>,
+    trust: impl FnOnce(&HostKey) -> bool,
+) -> Result<CertificateCheckStatus, git2::Error> {
+    let hostkey = match cert.as_hostkey() {
+        Some(hostkey) => hostkey,
+        None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+    };
+    let host = match port {
+        Some(port) if port != 22 => format!("[{}]:{}", host, port),
+        _ => host.to_owned(),
+    };
+    let (key, key_type, hash) = match (
+        hostkey.hostkey(),
+        hostkey.hostkey_type(),
+        hostkey.hash_sha256(),
+    ) {
+        (Some(key), Some(key_type), Some(hash)) if !matches!(key_type, SshHostKeyType::Unknown) => {
+            (key, key_type, hash)
+        }
+        // Old libssh2 versions don't give the raw key, nothing to check it against
+        _ => return Err(HostKeyError::Unsupported(host).into_error()),
+    };
+
+    let encoded_key = base64(key);
+    let host_key = HostKey {
+        host,
+        key_type: key_type.name().to_owned(),
+        fingerprint: format!("SHA256:{}", base64(hash).trim_end_matches('=')),
+    };
+
+    let status =
+        get_status(host_key, &encoded_key).map_err(|e| HostKeyError::Lookup(e).into_error())?;
+    match status {
+        HostKeyStatus::Known => Ok(CertificateCheckStatus::CertificateOk),
+        HostKeyStatus::Changed(host_key) => Err(HostKeyError::Changed(host_key).into_error()),
+        HostKeyStatus::Revoked(host_key) => Err(HostKeyError::Revoked(host_key).into_error()),
+        HostKeyStatus::Unknown(host_key) => {
+            if !trust(&host_key) {
+                return Err(HostKeyError::Untrusted(host_key).into_error());
+            }
+            add_known_host(&host_key, &encoded_key)
+                .map_err(|e| git2::Error::from_str(&e.to_string()))?;
+            Ok(CertificateCheckStatus::CertificateOk)
+        }
+    }
+}
+
+fn get_status(host_key: HostKey, encoded_key: &str) -> Result<HostKeyStatus, String> {
+    let known_hosts = get_known_hosts(&host_key.host)?;
+
+    // Revoked no matter what other lines say
+    if known_hosts
+        .iter()
+        .any(|known| known.marker == Some(Marker::Revoked) && known.key == encoded_key)
+    {
+        return Ok(HostKeyStatus::Revoked(host_key));
+    }
+
+    let mut changed = false;
+    // Keys of certificate authorities can't be the host key
+    for known in known_hosts.iter().filter(|known| known.marker.is_none()) {
+        if known.key_type != host_key.key_type {
+            continue;
+        }
+        if known.key == encoded_key {
+            return Ok(HostKeyStatus::Known);
+        }
+        changed = true;
+    }
+
+    // A key of another type doesn't make this one wrong, as ssh does.
+    Ok(if changed {
+        HostKeyStatus::Changed(host_key)
+    } else {
+        HostKeyStatus::Unknown(host_key)
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Marker {
+    Revoked,
+    CertAuthority,
+}
+
+// A line of known_hosts: `[@marker] <hosts> <key type> <key> [comment]`
+#[derive(Debug, PartialEq)]
+struct KnownHost {
+    marker: Option<Marker>,
+    hosts: String,
+    key_type: String,
+    key: String,
+}
+
+impl KnownHost {
+    // `None` for comments, blank lines and markers ssh doesn't know either
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut parts = line.split_whitespace();
+        let mut hosts = parts.next()?;
+        let marker = match hosts {
+            "@revoked" => Some(Marker::Revoked),
+            "@cert-authority" => Some(Marker::CertAuthority),
+            marker if marker.starts_with('@') => return None,
+            _ => None,
+        };
+        if marker.is_some() {
+            hosts = parts.next()?;
+        }
+
+        Some(KnownHost {
+            marker,
+            hosts: hosts.to_owned(),
+            key_type: parts.next()?.to_owned(),
+            key: parts.next()?.to_owned(),
+        })
+    }
+
+    // `|1|salt|hash`, only `ssh-keygen` can tell which host it is
+    fn is_hashed(&self) -> bool {
+        self.hosts.starts_with("|1|")
+    }
+
+    // Comma separated patterns, any of them matches unless a negated one (`!pattern`) does.
+    fn matches(&self, host: &str) -> bool {
+        let mut result = false;
+        for pattern in self.hosts.split(',') {
+            match pattern.strip_prefix('!') {
+                Some(pattern) if wildcard_matches(pattern, host) => return false,
+                Some(_) => {}
+                None => result = result || wildcard_matches(pattern, host),
+            }
+        }
+        result
+    }
+}
+
+// Lines of every known_hosts file that apply to `host`
+fn get_known_hosts(host: &str) -> Result<Vec<KnownHost>, String> {
+    let mut result = vec![];
+    for file in get_known_hosts_files() {
+        let content = fs::read_to_string(&file)
+            .map_err(|e| format!("Can't read {}: {}", file.display(), e))?;
+        let lines = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Some((i + 1, KnownHost::parse(line)?)))
+            .collect::<Vec<_>>();
+
+        let hashed_lines = if lines.iter().any(|(_, known)| known.is_hashed()) {
+            find_hashed_lines(&file, host)?
+        } else {
+            vec![]
+        };
+        result.extend(
+            lines
+                .into_iter()
+                .filter(|(number, known)| {
+                    if known.is_hashed() {
+                        hashed_lines.contains(number)
+                    } else {
+                        known.matches(host)
+                    }
+                })
+                .map(|(_, known)| known),
+        );
+    }
+    Ok(result)
+}
+
+// Numbers of the lines `ssh-keygen -F` finds `host` in. It finds the plain ones too, only
+// the hashed ones are taken from it.
+fn find_hashed_lines(file: &Path, host: &str) -> Result<Vec<usize>, String> {
+    let output = Command::new("ssh-keygen")
+        .arg("-F")
+        .arg(host)
+        .arg("-f")
+        .arg(file)
+        .output()
+        .map_err(|e| format!("Can't run ssh-keygen: {}", e))?;
+
+    match output.status.code() {
+        Some(0) => Ok(parse_found_lines(&String::from_utf8_lossy(&output.stdout))),
+        // Not found
+        Some(1) => Ok(vec![]),
+        _ => Err(format!(
+            "ssh-keygen can't read {}: {}",
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+// Every line found comes after `# Host <host> found: line <number> [REVOKED|CA]`
+fn parse_found_lines(output: &str) -> Vec<usize> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (_, found) = line.strip_prefix("# Host ")?.rsplit_once(" found: line ")?;
+            found.split_whitespace().next()?.parse().ok()
+        })
+        .collect()
+}
+
+fn get_known_hosts_files() -> Vec<PathBuf> {
+    let mut files = vec![];
+    if let Some(known_hosts) = get_user_known_hosts() {
+        files.push(known_hosts);
+    }
+    files.push(PathBuf::from("/etc/ssh/ssh_known_hosts"));
+    files.into_iter().filter(|file| file.is_file()).collect()
+}
+
+fn get_user_known_hosts() -> Option<PathBuf> {
+    env::var("HOME")
+        .or_else(|_| env::var("USERPROFILE"))
+        .ok()
+        .map(|home| PathBuf::from(home).join(".ssh").join("known_hosts"))
+}
+
+fn add_known_host(host_key: &HostKey, encoded_key: &str) -> std::io::Result<()> {
+    let known_hosts = get_user_known_hosts()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No home directory"))?;
+    if let Some(dir) = known_hosts.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(known_hosts)?;
+    writeln!(
+        file,
+        "{} {} {}",
+        host_key.host, host_key.key_type, encoded_key
+    )
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(value >> (18 - i * 6) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn parses_known_hosts_lines() {
+        assert_eq!(
+            KnownHost::parse("github.com,140.82.121.4 ssh-ed25519 AAAAkey comment"),
+            Some(KnownHost {
+                marker: None,
+                hosts: "github.com,140.82.121.4".to_owned(),
+                key_type: "ssh-ed25519".to_owned(),
+                key: "AAAAkey".to_owned(),
+            })
+        );
+        assert_eq!(
+            KnownHost::parse("@revoked * ssh-rsa AAAAkey"),
+            Some(KnownHost {
+                marker: Some(Marker::Revoked),
+                hosts: "*".to_owned(),
+                key_type: "ssh-rsa".to_owned(),
+                key: "AAAAkey".to_owned(),
+            })
+        );
+        assert_eq!(
+            KnownHost::parse("@cert-authority *.example.com ssh-rsa AAAAkey")
+                .and_then(|known| known.marker),
+            Some(Marker::CertAuthority)
+        );
+        assert_eq!(KnownHost::parse("# github.com ssh-rsa AAAAkey"), None);
+        assert_eq!(KnownHost::parse("   "), None);
+        assert_eq!(KnownHost::parse("@unknown * ssh-rsa AAAAkey"), None);
+        assert_eq!(KnownHost::parse("github.com ssh-rsa"), None);
+    }
+
+    #[test]
+    fn matches_known_hosts_patterns() {
+        let known = |hosts: &str| KnownHost::parse(&format!("{} ssh-rsa AAAAkey", hosts)).unwrap();
+
+        assert!(known("github.com,gitlab.com").matches("gitlab.com"));
+        assert!(known("*.example.com,!bad.example.com").matches("git.example.com"));
+        assert!(!known("*.example.com,!bad.example.com").matches("bad.example.com"));
+        assert!(known("[example.com]:2222").matches("[example.com]:2222"));
+        assert!(!known("example.com").matches("[example.com]:2222"));
+        assert!(known("|1|salt|hash").is_hashed());
+        assert!(!known("|1|salt|hash").matches("example.com"));
+    }
+
+    #[test]
+    fn parses_lines_found_by_ssh_keygen() {
+        let output = "# Host example.com found: line 3 REVOKED\n\
+            @revoked * ssh-rsa AAAAkey\n\
+            # Host example.com found: line 7 \n\
+            |1|salt|hash ssh-rsa AAAAkey\n";
+
+        assert_eq!(parse_found_lines(output), vec![3, 7]);
+    }
+}
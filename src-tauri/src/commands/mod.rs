@@ -1,5 +1,7 @@
+mod branch;
 mod checkout;
 mod commit;
+mod credentials;
 mod diff_settings;
 mod fetch;
 mod get_commit;
@@ -8,13 +10,24 @@ mod get_diff;
 mod get_last_repo;
 mod get_refs;
 mod get_working_dir;
+mod graph_settings;
+mod known_hosts;
 mod open_repo;
+mod pull;
+mod push;
+mod resolve_revision;
+mod search_commits;
 pub mod serializer;
 mod stage_unstage;
+mod tag;
+mod update_commits;
+mod verify_tag;
 mod watch_repo;
 
+pub use branch::*;
 pub use checkout::*;
 pub use commit::*;
+pub use credentials::*;
 pub use diff_settings::*;
 pub use fetch::*;
 pub use get_commit::*;
@@ -23,6 +36,15 @@ pub use get_diff::*;
 pub use get_last_repo::*;
 pub use get_refs::*;
 pub use get_working_dir::*;
+pub use graph_settings::*;
+pub use known_hosts::*;
 pub use open_repo::*;
+pub use pull::*;
+pub use push::*;
+pub use resolve_revision::*;
+pub use search_commits::*;
 pub use stage_unstage::*;
+pub use tag::*;
+pub use update_commits::*;
+pub use verify_tag::*;
 pub use watch_repo::*;
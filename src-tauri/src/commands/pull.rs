@@ -0,0 +1,446 @@
+use git2::{
+    build::CheckoutBuilder, AnnotatedCommit, BranchType, Config, ErrorCode, Oid, RebaseOptions,
+    Repository, StatusOptions,
+};
+use logging_timer::time;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
+use tauri::{State, Window};
+
+use super::{fetch_remote, register_fetch, unregister_fetch, FetchError, FetchRequest};
+use crate::AppState;
+
+#[derive(Serialize)]
+pub enum PullError {
+    Read(String),
+    Fetch(FetchError),
+    DetachedHead,
+    NoUpstream,
+    // Fast-forward only, but the branch has diverged from its upstream
+    NotFastForward,
+    // Local changes would be overwritten
+    LocalChanges,
+}
+
+impl From<git2::Error> for PullError {
+    fn from(value: git2::Error) -> Self {
+        match value.code() {
+            ErrorCode::Conflict => PullError::LocalChanges,
+            _ => PullError::Read(value.message().to_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub enum PullMode {
+    FastForwardOnly,
+    Merge,
+    Rebase,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum PullOutcome {
+    UpToDate,
+    // New id of the branch
+    FastForwarded(String),
+    Merged(String),
+    Rebased(String),
+    // The merge or rebase is left in progress, as git does
+    Conflicts(Vec<String>),
+}
+
+/// Fetches the upstream of the current branch and integrates it. Without `mode` it
+/// follows `branch.<name>.rebase`, `pull.rebase` and `pull.ff`, like `git pull`.
+/// The fetch can be stopped through `cancel_fetch`, same as `fetch`.
+#[time]
+#[tauri::command(async)]
+pub fn pull(
+    path: String,
+    mode: Option<PullMode>,
+    window: Window,
+    state: State<AppState>,
+) -> Result<PullOutcome, PullError> {
+    let cancelled = register_fetch(&state, &path);
+    let result = pull_upstream(&path, mode, Some(&window), &cancelled);
+    unregister_fetch(&state, &path, &cancelled);
+
+    result
+}
+
+fn pull_upstream(
+    path: &str,
+    mode: Option<PullMode>,
+    window: Option<&Window>,
+    cancelled: &AtomicBool,
+) -> Result<PullOutcome, PullError> {
+    let repo = Repository::open(path)?;
+    let head = repo.head()?;
+    let branch_name = match head.is_branch().then(|| head.shorthand()).flatten() {
+        Some(branch) => branch.to_owned(),
+        None => return Err(PullError::DetachedHead),
+    };
+    let refname = format!("refs/heads/{}", branch_name);
+    let remote = match repo.branch_upstream_remote(&refname) {
+        Ok(remote) => remote.as_str().unwrap_or_default().to_owned(),
+        Err(_) => return Err(PullError::NoUpstream),
+    };
+
+    fetch_remote(
+        path.to_owned(),
+        &remote,
+        &FetchRequest::default(),
+        window,
+        cancelled,
+    )
+    .map_err(PullError::Fetch)?;
+
+    let upstream = repo
+        .find_branch(&branch_name, BranchType::Local)?
+        .upstream()
+        .map_err(|_| PullError::NoUpstream)?
+        .into_reference();
+    let upstream_commit = repo.reference_to_annotated_commit(&upstream)?;
+
+    let config = repo.config()?;
+    let mode = mode.unwrap_or_else(|| get_configured_mode(&config, &branch_name));
+    // `pull.ff=false` always creates a merge commit
+    let no_ff = mode == PullMode::Merge && config.get_bool("pull.ff").ok() == Some(false);
+
+    let (analysis, _) = repo.merge_analysis(&[&upstream_commit])?;
+    if analysis.is_up_to_date() {
+        return Ok(PullOutcome::UpToDate);
+    }
+    if analysis.is_fast_forward() && !no_ff {
+        fast_forward(&repo, &refname, upstream_commit.id())?;
+        return Ok(PullOutcome::FastForwarded(upstream_commit.id().to_string()));
+    }
+
+    match mode {
+        PullMode::FastForwardOnly => Err(PullError::NotFastForward),
+        PullMode::Merge => merge(
+            &repo,
+            &upstream_commit,
+            upstream.shorthand().unwrap_or_default(),
+        ),
+        PullMode::Rebase => rebase(&repo, &upstream_commit),
+    }
+}
+
+// `branch.<name>.rebase` takes precedence over `pull.rebase`. Any value other than
+// false (`merges`, `interactive`) is a rebase.
+fn get_configured_mode(config: &Config, branch_name: &str) -> PullMode {
+    let rebase = config
+        .get_string(&format!("branch.{}.rebase", branch_name))
+        .or_else(|_| config.get_string("pull.rebase"))
+        .map(|value| !matches!(value.to_lowercase().as_str(), "false" | "no" | "off" | "0"))
+        .unwrap_or(false);
+
+    if rebase {
+        PullMode::Rebase
+    } else if config.get_string("pull.ff").as_deref() == Ok("only") {
+        PullMode::FastForwardOnly
+    } else {
+        PullMode::Merge
+    }
+}
+
+fn fast_forward(repo: &Repository, refname: &str, target: Oid) -> Result<(), PullError> {
+    let tree = repo.find_commit(target)?.tree()?;
+
+    let mut opts = CheckoutBuilder::new();
+    opts.safe();
+    repo.checkout_tree(tree.as_object(), Some(&mut opts))?;
+
+    repo.find_reference(refname)?
+        .set_target(target, "pull: Fast-forward")?;
+
+    Ok(())
+}
+
+fn merge(
+    repo: &Repository,
+    upstream: &AnnotatedCommit,
+    upstream_name: &str,
+) -> Result<PullOutcome, PullError> {
+    let mut opts = CheckoutBuilder::new();
+    opts.safe();
+    repo.merge(&[upstream], None, Some(&mut opts))?;
+
+    let mut index = repo.index()?;
+    if index.has_conflicts() {
+        return Ok(PullOutcome::Conflicts(get_conflicts(repo)?));
+    }
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = repo.signature()?;
+    let head_commit = repo.head()?.peel_to_commit()?;
+    let upstream_commit = repo.find_commit(upstream.id())?;
+    let oid = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &format!("Merge remote-tracking branch '{}'", upstream_name),
+        &tree,
+        &[&head_commit, &upstream_commit],
+    )?;
+    repo.cleanup_state()?;
+
+    Ok(PullOutcome::Merged(oid.to_string()))
+}
+
+fn rebase(repo: &Repository, upstream: &AnnotatedCommit) -> Result<PullOutcome, PullError> {
+    // The working dir has to be clean, same as `git rebase`
+    let mut status_opts = StatusOptions::new();
+    status_opts.include_untracked(false).include_ignored(false);
+    if !repo.statuses(Some(&mut status_opts))?.is_empty() {
+        return Err(PullError::LocalChanges);
+    }
+
+    let signature = repo.signature()?;
+    let mut opts = RebaseOptions::new();
+    let mut rebase = repo.rebase(None, Some(upstream), None, Some(&mut opts))?;
+
+    while let Some(operation) = rebase.next() {
+        operation?;
+        if repo.index()?.has_conflicts() {
+            return Ok(PullOutcome::Conflicts(get_conflicts(repo)?));
+        }
+        match rebase.commit(None, &signature, None) {
+            Ok(_) => {}
+            // The change is already upstream, so the commit gets dropped
+            Err(e) if e.code() == ErrorCode::Applied => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    rebase.finish(Some(&signature))?;
+
+    let head = repo.head()?.peel_to_commit()?;
+    Ok(PullOutcome::Rebased(head.id().to_string()))
+}
+
+fn get_conflicts(repo: &Repository) -> Result<Vec<String>, PullError> {
+    Ok(repo
+        .index()?
+        .conflicts()?
+        .filter_map(|conflict| conflict.ok())
+        .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+        .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{IndexAddOption, Signature};
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    struct Remotes {
+        _dir: TempDir,
+        remote: Repository,
+        local: Repository,
+        path: String,
+    }
+
+    // A bare remote with `file` on `main`, and a clone tracking it
+    fn setup() -> Remotes {
+        let dir = TempDir::new().unwrap();
+        let remote = Repository::init_bare(dir.path().join("remote.git")).unwrap();
+        let base = commit_on_remote(&remote, &[("file", "base\n")]);
+
+        let local = Repository::init(dir.path().join("local")).unwrap();
+        let mut config = local.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        local
+            .remote("origin", dir.path().join("remote.git").to_str().unwrap())
+            .unwrap();
+        local
+            .find_remote("origin")
+            .unwrap()
+            .fetch(&["refs/heads/main:refs/remotes/origin/main"], None, None)
+            .unwrap();
+        local
+            .branch("main", &local.find_commit(base).unwrap(), false)
+            .unwrap()
+            .set_upstream(Some("origin/main"))
+            .unwrap();
+        local.set_head("refs/heads/main").unwrap();
+        local
+            .checkout_head(Some(CheckoutBuilder::new().force()))
+            .unwrap();
+
+        let path = local.workdir().unwrap().to_str().unwrap().to_owned();
+        Remotes {
+            _dir: dir,
+            remote,
+            local,
+            path,
+        }
+    }
+
+    // Commits `files` over the tree of `main` on the remote, as if someone else pushed
+    fn commit_on_remote(remote: &Repository, files: &[(&str, &str)]) -> Oid {
+        let parent = remote
+            .find_reference("refs/heads/main")
+            .ok()
+            .map(|reference| reference.peel_to_commit().unwrap());
+        let parent_tree = parent.as_ref().map(|parent| parent.tree().unwrap());
+        let mut builder = remote.treebuilder(parent_tree.as_ref()).unwrap();
+        for (name, content) in files {
+            let blob = remote.blob(content.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = remote.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Other", "other@example.com").unwrap();
+        remote
+            .commit(
+                Some("refs/heads/main"),
+                &signature,
+                &signature,
+                "remote",
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+    }
+
+    // Writes `files` to the working dir and commits them on HEAD
+    fn commit_locally(local: &Repository, files: &[(&str, &str)]) -> Oid {
+        let workdir = local.workdir().unwrap();
+        for (name, content) in files {
+            fs::write(workdir.join(name), content).unwrap();
+        }
+        let mut index = local.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = local.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = local.signature().unwrap();
+        let parent = local.head().unwrap().peel_to_commit().unwrap();
+        local
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "local",
+                &tree,
+                &[&parent],
+            )
+            .unwrap()
+    }
+
+    fn pull(remotes: &Remotes, mode: PullMode) -> Result<PullOutcome, PullError> {
+        pull_upstream(&remotes.path, Some(mode), None, &AtomicBool::new(false))
+    }
+
+    fn head(repo: &Repository) -> git2::Commit {
+        repo.head().unwrap().peel_to_commit().unwrap()
+    }
+
+    fn read(remotes: &Remotes, name: &str) -> String {
+        fs::read_to_string(Path::new(&remotes.path).join(name)).unwrap()
+    }
+
+    #[test]
+    fn fast_forwards() {
+        let remotes = setup();
+        let pushed = commit_on_remote(&remotes.remote, &[("file", "changed\n")]);
+
+        let outcome = pull(&remotes, PullMode::FastForwardOnly).ok().unwrap();
+
+        assert!(matches!(outcome, PullOutcome::FastForwarded(id) if id == pushed.to_string()));
+        assert_eq!(head(&remotes.local).id(), pushed);
+        assert_eq!(read(&remotes, "file"), "changed\n");
+        let outcome = pull(&remotes, PullMode::FastForwardOnly).ok().unwrap();
+        assert!(matches!(outcome, PullOutcome::UpToDate));
+    }
+
+    #[test]
+    fn refuses_to_fast_forward_diverged_branches() {
+        let remotes = setup();
+        commit_on_remote(&remotes.remote, &[("other", "remote\n")]);
+        let local = commit_locally(&remotes.local, &[("mine", "local\n")]);
+
+        let result = pull(&remotes, PullMode::FastForwardOnly);
+
+        assert!(matches!(result, Err(PullError::NotFastForward)));
+        assert_eq!(head(&remotes.local).id(), local);
+    }
+
+    #[test]
+    fn merges_diverged_branches() {
+        let remotes = setup();
+        let pushed = commit_on_remote(&remotes.remote, &[("other", "remote\n")]);
+        let local = commit_locally(&remotes.local, &[("mine", "local\n")]);
+
+        let outcome = pull(&remotes, PullMode::Merge).ok().unwrap();
+
+        let head = head(&remotes.local);
+        assert!(matches!(outcome, PullOutcome::Merged(id) if id == head.id().to_string()));
+        assert_eq!(head.parent_ids().collect::<Vec<_>>(), vec![local, pushed]);
+        assert_eq!(read(&remotes, "other"), "remote\n");
+        assert_eq!(read(&remotes, "mine"), "local\n");
+        assert_eq!(remotes.local.state(), git2::RepositoryState::Clean);
+    }
+
+    #[test]
+    fn rebases_diverged_branches() {
+        let remotes = setup();
+        let pushed = commit_on_remote(&remotes.remote, &[("other", "remote\n")]);
+        commit_locally(&remotes.local, &[("mine", "local\n")]);
+
+        let outcome = pull(&remotes, PullMode::Rebase).ok().unwrap();
+
+        let head = head(&remotes.local);
+        assert!(matches!(outcome, PullOutcome::Rebased(id) if id == head.id().to_string()));
+        assert_eq!(head.parent_ids().collect::<Vec<_>>(), vec![pushed]);
+        assert_eq!(head.message(), Some("local"));
+        assert_eq!(read(&remotes, "other"), "remote\n");
+        assert_eq!(read(&remotes, "mine"), "local\n");
+    }
+
+    #[test]
+    fn leaves_conflicting_merges_in_progress() {
+        let remotes = setup();
+        commit_on_remote(&remotes.remote, &[("file", "remote\n")]);
+        commit_locally(&remotes.local, &[("file", "local\n")]);
+
+        let outcome = pull(&remotes, PullMode::Merge).ok().unwrap();
+
+        assert!(matches!(outcome, PullOutcome::Conflicts(files) if files == vec!["file"]));
+        assert_eq!(remotes.local.state(), git2::RepositoryState::Merge);
+    }
+
+    #[test]
+    fn leaves_conflicting_rebases_in_progress() {
+        let remotes = setup();
+        commit_on_remote(&remotes.remote, &[("file", "remote\n")]);
+        commit_locally(&remotes.local, &[("file", "local\n")]);
+
+        let outcome = pull(&remotes, PullMode::Rebase).ok().unwrap();
+
+        assert!(matches!(outcome, PullOutcome::Conflicts(files) if files == vec!["file"]));
+        assert_eq!(remotes.local.state(), git2::RepositoryState::RebaseMerge);
+    }
+
+    #[test]
+    fn stops_when_the_fetch_is_cancelled() {
+        let remotes = setup();
+        let base = head(&remotes.local).id();
+        commit_on_remote(&remotes.remote, &[("file", "changed\n")]);
+
+        let result = pull_upstream(
+            &remotes.path,
+            Some(PullMode::Merge),
+            None,
+            &AtomicBool::new(true),
+        );
+
+        assert!(matches!(
+            result,
+            Err(PullError::Fetch(FetchError::Cancelled))
+        ));
+        assert_eq!(head(&remotes.local).id(), base);
+    }
+}
@@ -0,0 +1,542 @@
+use git2::{Direction, ErrorCode, Oid, Remote, Repository};
+use logging_timer::time;
+use serde::{Deserialize, Serialize};
+use tauri::Window;
+
+use super::{get_remote_callbacks, HostKeyError};
+
+#[derive(Serialize)]
+pub enum PushError {
+    Read(String),
+    // Pushing the current branch needs one
+    DetachedHead,
+    NoRemote,
+    // libgit2 refuses before pushing anything when the remote has commits we don't have
+    NotFastForward,
+    HostKey(HostKeyError),
+}
+
+impl From<git2::Error> for PushError {
+    fn from(value: git2::Error) -> Self {
+        if let Some(error) = HostKeyError::from_error(&value) {
+            return PushError::HostKey(error);
+        }
+        match value.code() {
+            ErrorCode::NotFastForward => PushError::NotFastForward,
+            _ => PushError::Read(value.message().to_owned()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PushRequest {
+    // Defaults to the push remote of the current branch
+    #[serde(default)]
+    pub remote: Option<String>,
+    // Empty pushes the current branch, and sets its upstream if it doesn't have one.
+    #[serde(default)]
+    pub refspecs: Vec<String>,
+    // Remote refs to force push only if they're still where we expect them to be.
+    #[serde(default)]
+    pub force_with_lease: Vec<Lease>,
+}
+
+#[derive(Deserialize)]
+pub struct Lease {
+    // Full name of the ref on the remote, e.g. `refs/heads/main`
+    pub reference: String,
+    // `None` expects it where its remote-tracking ref is, as `--force-with-lease` does.
+    pub expected: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PushedRef {
+    // Full name of the ref on the remote
+    pub reference: String,
+    pub status: PushStatus,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum PushStatus {
+    Updated,
+    // Reason given by the remote, e.g. non-fast-forward
+    Rejected(String),
+    // The lease didn't hold, so it wasn't pushed
+    Stale {
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+pub struct PushOutcome {
+    pub refs: Vec<PushedRef>,
+    // The branch got pushed, but setting its upstream to where it went failed
+    pub upstream_error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PushProgress {
+    pub remote: String,
+    pub progress: PushStage,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum PushStage {
+    Packing {
+        current: usize,
+        total: usize,
+    },
+    Transfer {
+        current: usize,
+        total: usize,
+        bytes: usize,
+    },
+}
+
+/// Progress gets emitted to `push-progress` as `PushProgress`.
+/// Returns how every ref went, a rejected ref doesn't fail the whole push.
+#[time]
+#[tauri::command(async)]
+pub fn push(path: String, request: PushRequest, window: Window) -> Result<PushOutcome, PushError> {
+    let repo = Repository::open(path)?;
+
+    if !request.refspecs.is_empty() {
+        let remote = match request.remote {
+            Some(remote) => remote,
+            None => get_default_remote(&repo, None).ok_or(PushError::NoRemote)?,
+        };
+        let mut remote = repo.find_remote(&remote)?;
+        let refs = push_with_lease(
+            &repo,
+            &mut remote,
+            request.refspecs,
+            &request.force_with_lease,
+            Some(&window),
+        )?;
+        return Ok(PushOutcome {
+            refs,
+            upstream_error: None,
+        });
+    }
+
+    let head = repo.head()?;
+    let branch = match head.is_branch().then(|| head.shorthand()).flatten() {
+        Some(branch) => branch.to_owned(),
+        None => return Err(PushError::DetachedHead),
+    };
+    let remote = match request.remote {
+        Some(remote) => remote,
+        None => get_default_remote(&repo, Some(&branch)).ok_or(PushError::NoRemote)?,
+    };
+    let mut remote = repo.find_remote(&remote)?;
+    let remote_name = remote.name().unwrap_or_default().to_owned();
+
+    // Same as `push.default=simple`: the branch goes to its upstream when it's on that
+    // remote, or to a branch with the same name otherwise.
+    let config = repo.config()?;
+    let destination = config
+        .get_string(&format!("branch.{}.merge", branch))
+        .ok()
+        .filter(|_| {
+            config
+                .get_string(&format!("branch.{}.remote", branch))
+                .map_or(false, |upstream_remote| upstream_remote == remote_name)
+        })
+        .unwrap_or_else(|| format!("refs/heads/{}", branch));
+    let refspec = format!("refs/heads/{}:{}", branch, destination);
+
+    let refs = push_with_lease(
+        &repo,
+        &mut remote,
+        vec![refspec],
+        &request.force_with_lease,
+        Some(&window),
+    )?;
+
+    let updated = refs
+        .iter()
+        .all(|pushed| matches!(pushed.status, PushStatus::Updated));
+    let upstream_error = if updated {
+        let upstream = destination.trim_start_matches("refs/heads/");
+        set_missing_upstream(&repo, &branch, &format!("{}/{}", remote_name, upstream))
+            .err()
+            .map(|e| e.message().to_owned())
+    } else {
+        None
+    };
+
+    Ok(PushOutcome {
+        refs,
+        upstream_error,
+    })
+}
+
+fn set_missing_upstream(
+    repo: &Repository,
+    branch: &str,
+    upstream: &str,
+) -> Result<(), git2::Error> {
+    let mut local_branch = repo.find_branch(branch, git2::BranchType::Local)?;
+    if local_branch.upstream().is_err() {
+        local_branch.set_upstream(Some(upstream))?;
+    }
+    Ok(())
+}
+
+/// Deletes the branch on the remote, along with its remote-tracking ref.
+#[time]
+#[tauri::command(async)]
+pub fn delete_remote_branch(
+    path: String,
+    remote: String,
+    name: String,
+    window: Window,
+) -> Result<Vec<PushedRef>, PushError> {
+    let repo = Repository::open(path)?;
+    let mut remote = repo.find_remote(&remote)?;
+
+    Ok(push_refspecs(
+        &repo,
+        &mut remote,
+        &[format!(":refs/heads/{}", name)],
+        Some(&window),
+    )?)
+}
+
+// Where `git push` would push to: the branch's push remote, the default one, the
+// upstream's remote, or `origin` (or the only remote there is).
+fn get_default_remote(repo: &Repository, branch: Option<&str>) -> Option<String> {
+    let config = repo.config().ok()?;
+    let configured = branch
+        .and_then(|branch| {
+            config
+                .get_string(&format!("branch.{}.pushRemote", branch))
+                .ok()
+        })
+        .or_else(|| config.get_string("remote.pushDefault").ok())
+        .or_else(|| {
+            branch.and_then(|branch| config.get_string(&format!("branch.{}.remote", branch)).ok())
+        });
+    if configured.is_some() {
+        return configured;
+    }
+
+    let remotes = repo.remotes().ok()?;
+    let remotes = remotes.iter().flatten().collect::<Vec<_>>();
+    match remotes.as_slice() {
+        [remote] => Some(remote.to_string()),
+        remotes => remotes
+            .iter()
+            .find(|remote| **remote == "origin")
+            .map(|remote| remote.to_string()),
+    }
+}
+
+// libgit2 doesn't support leases, so the leased refs get force pushed and checked while
+// negotiating the push, where the remote tells where its refs are. If any doesn't hold
+// nothing gets pushed, so it's tried again without them.
+fn push_with_lease(
+    repo: &Repository,
+    remote: &mut Remote,
+    refspecs: Vec<String>,
+    leases: &[Lease],
+    window: Option<&Window>,
+) -> Result<Vec<PushedRef>, PushError> {
+    let mut expected = vec![];
+    let mut to_push = vec![];
+    for refspec in refspecs {
+        let destination = get_refspec_destination(&refspec);
+        let lease = match leases.iter().find(|lease| lease.reference == destination) {
+            Some(lease) => lease,
+            None => {
+                to_push.push(refspec);
+                continue;
+            }
+        };
+
+        let oid = match &lease.expected {
+            Some(expected) => Some(Oid::from_str(expected)?),
+            None => get_tracking_target(repo, remote, &destination),
+        };
+        expected.push((destination, oid));
+        to_push.push(format!("+{}", refspec.trim_start_matches('+')));
+    }
+
+    let mut stale = vec![];
+    while !to_push.is_empty() {
+        let previous = stale.len();
+        match push_leased(repo, remote, &to_push, &expected, &mut stale, window) {
+            Ok(mut result) => {
+                result.extend(stale);
+                return Ok(result);
+            }
+            Err(_) if stale.len() > previous => {
+                let new_stale = &stale[previous..];
+                to_push.retain(|refspec| {
+                    let destination = get_refspec_destination(refspec);
+                    !new_stale
+                        .iter()
+                        .any(|pushed| pushed.reference == destination)
+                });
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(stale)
+}
+
+// `[+]<src>:<dst>`, where a missing `dst` is the same as `src`
+fn get_refspec_destination(refspec: &str) -> String {
+    let refspec = refspec.trim_start_matches('+');
+    match refspec.split_once(':') {
+        Some((_, destination)) => destination.to_owned(),
+        None => refspec.to_owned(),
+    }
+}
+
+fn get_tracking_target(repo: &Repository, remote: &Remote, reference: &str) -> Option<Oid> {
+    remote
+        .refspecs()
+        .filter(|refspec| refspec.direction() == Direction::Fetch)
+        .find(|refspec| refspec.src_matches(reference))
+        .and_then(|refspec| refspec.transform(reference).ok())
+        .and_then(|tracking| tracking.as_str().map(|name| name.to_owned()))
+        .and_then(|tracking| repo.refname_to_id(&tracking).ok())
+}
+
+/// Pushes `refspecs` as they are, reporting the progress to `window` if there's one.
+/// The remote-tracking refs get updated for the refs that went through.
+pub fn push_refspecs(
+    repo: &Repository,
+    remote: &mut Remote,
+    refspecs: &[String],
+    window: Option<&Window>,
+) -> Result<Vec<PushedRef>, git2::Error> {
+    push_leased(repo, remote, refspecs, &[], &mut vec![], window)
+}
+
+// `expected` has where the remote refs should be (`None` for missing) to push them.
+// When any isn't, it gets added to `stale` and the push fails without updating anything.
+fn push_leased(
+    repo: &Repository,
+    remote: &mut Remote,
+    refspecs: &[String],
+    expected: &[(String, Option<Oid>)],
+    stale: &mut Vec<PushedRef>,
+    window: Option<&Window>,
+) -> Result<Vec<PushedRef>, git2::Error> {
+    let remote_name = remote.name().unwrap_or_default().to_owned();
+    let mut result = vec![];
+    {
+        let (mut cb, used_credentials) = get_remote_callbacks(repo, remote, window);
+        cb.push_negotiation(|updates| {
+            let found = stale.len();
+            for update in updates {
+                let reference = update.dst_refname().unwrap_or_default();
+                let expected = match expected.iter().find(|(name, _)| name == reference) {
+                    Some((_, expected)) => *expected,
+                    None => continue,
+                };
+                let actual = Some(update.src()).filter(|oid| !oid.is_zero());
+                if expected != actual {
+                    stale.push(PushedRef {
+                        reference: reference.to_owned(),
+                        status: PushStatus::Stale {
+                            expected: expected.map(|oid| oid.to_string()),
+                            actual: actual.map(|oid| oid.to_string()),
+                        },
+                    });
+                }
+            }
+            if stale.len() > found {
+                return Err(git2::Error::from_str("Stale lease"));
+            }
+            Ok(())
+        });
+        cb.push_update_reference(|reference, status| {
+            result.push(PushedRef {
+                reference: reference.to_owned(),
+                status: match status {
+                    Some(reason) => PushStatus::Rejected(reason.to_owned()),
+                    None => PushStatus::Updated,
+                },
+            });
+            Ok(())
+        });
+        if let Some(window) = window {
+            let name = remote_name.clone();
+            cb.pack_progress(move |_stage, current, total| {
+                let progress = PushProgress {
+                    remote: name.clone(),
+                    progress: PushStage::Packing { current, total },
+                };
+                window.emit("push-progress", progress).ok();
+            });
+            let name = remote_name.clone();
+            cb.push_transfer_progress(move |current, total, bytes| {
+                let progress = PushProgress {
+                    remote: name.clone(),
+                    progress: PushStage::Transfer {
+                        current,
+                        total,
+                        bytes,
+                    },
+                };
+                window.emit("push-progress", progress).ok();
+            });
+        }
+
+        let mut options = git2::PushOptions::new();
+        options.remote_callbacks(cb);
+        let pushed = remote.push(refspecs, Some(&mut options));
+        used_credentials.settle(&pushed);
+        pushed?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use tempfile::TempDir;
+
+    struct Remotes {
+        _dir: TempDir,
+        remote: Repository,
+        local: Repository,
+        other: Repository,
+    }
+
+    // A bare remote with one commit on `main`, and two clones of it
+    fn setup() -> Remotes {
+        let dir = TempDir::new().unwrap();
+        let remote = Repository::init_bare(dir.path().join("remote.git")).unwrap();
+        let url = dir.path().join("remote.git");
+        let url = url.to_str().unwrap();
+        let local = Repository::init(dir.path().join("local")).unwrap();
+        local.remote("origin", url).unwrap();
+        commit(&local, "first", &[]);
+        push_refspecs(
+            &local,
+            &mut local.find_remote("origin").unwrap(),
+            &["refs/heads/main:refs/heads/main".to_owned()],
+            None,
+        )
+        .unwrap();
+
+        let other = Repository::init(dir.path().join("other")).unwrap();
+        other.remote("origin", url).unwrap();
+        other
+            .find_remote("origin")
+            .unwrap()
+            .fetch(&["refs/heads/main:refs/heads/main"], None, None)
+            .unwrap();
+
+        Remotes {
+            _dir: dir,
+            remote,
+            local,
+            other,
+        }
+    }
+
+    // Commits on top of `parents` and moves `main` to it
+    fn commit(repo: &Repository, message: &str, parents: &[Oid]) -> Oid {
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let parents = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect::<Vec<_>>();
+        let oid = repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap();
+        repo.reference("refs/heads/main", oid, true, "test")
+            .unwrap();
+        oid
+    }
+
+    fn main_of(repo: &Repository) -> Oid {
+        repo.refname_to_id("refs/heads/main").unwrap()
+    }
+
+    fn push_main(repo: &Repository, leases: &[Lease]) -> Result<Vec<PushedRef>, PushError> {
+        push_with_lease(
+            repo,
+            &mut repo.find_remote("origin").unwrap(),
+            vec!["refs/heads/main:refs/heads/main".to_owned()],
+            leases,
+            None,
+        )
+    }
+
+    fn lease(expected: Option<Oid>) -> Lease {
+        Lease {
+            reference: "refs/heads/main".to_owned(),
+            expected: expected.map(|oid| oid.to_string()),
+        }
+    }
+
+    #[test]
+    fn pushes_when_lease_holds() {
+        let remotes = setup();
+        let rewritten = commit(&remotes.local, "rewritten", &[]);
+
+        // Expected where `origin/main` is
+        let result = push_main(&remotes.local, &[lease(None)]).ok().unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert!(matches!(result[0].status, PushStatus::Updated));
+        assert_eq!(main_of(&remotes.remote), rewritten);
+    }
+
+    #[test]
+    fn leaves_out_stale_leases() {
+        let remotes = setup();
+        let first = main_of(&remotes.remote);
+        let pushed = commit(&remotes.other, "other", &[first]);
+        push_main(&remotes.other, &[]).ok().unwrap();
+        commit(&remotes.local, "rewritten", &[]);
+
+        let result = push_main(&remotes.local, &[lease(Some(first))])
+            .ok()
+            .unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].reference, "refs/heads/main");
+        match &result[0].status {
+            PushStatus::Stale { expected, actual } => {
+                assert_eq!(*expected, Some(first.to_string()));
+                assert_eq!(*actual, Some(pushed.to_string()));
+            }
+            _ => panic!("lease should be stale"),
+        }
+        assert_eq!(main_of(&remotes.remote), pushed);
+    }
+
+    #[test]
+    fn refuses_non_fast_forward_without_lease() {
+        let remotes = setup();
+        let first = main_of(&remotes.remote);
+        commit(&remotes.local, "rewritten", &[]);
+
+        let result = push_main(&remotes.local, &[]);
+
+        assert!(matches!(result, Err(PushError::NotFastForward)));
+        assert_eq!(main_of(&remotes.remote), first);
+    }
+}
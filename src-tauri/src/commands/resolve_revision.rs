@@ -0,0 +1,43 @@
+use crate::graph_cache::{load_graph, GraphKey};
+use crate::positioned_commit::HistoryOptions;
+use git2::{Commit, Repository};
+use logging_timer::time;
+use serde::Serialize;
+
+use super::serializer::git_error::GitError;
+
+#[derive(Serialize)]
+pub struct ResolvedRevision {
+    id: String,
+    // Row on the graph built with the same options, `None` if it's not shown there or
+    // that graph hasn't been loaded for the current refs.
+    row: Option<usize>,
+}
+
+/// Finds the commit `revspec` points to (`HEAD~3`, `v1.2^{}`, `origin/main@{yesterday}`,
+/// a short hash...) and its row on the graph.
+/// The row is looked up in the graph `get_commits` cached, instead of walking the
+/// history again.
+#[time]
+#[tauri::command(async)]
+pub fn resolve_revision(
+    path: String,
+    revspec: String,
+    options: Option<HistoryOptions>,
+    app: tauri::AppHandle,
+) -> Result<ResolvedRevision, GitError> {
+    let repo = Repository::open(&path)?;
+    let options = options.unwrap_or_default();
+
+    let id = find_commit(&repo, &revspec)?.id().to_string();
+    let key = GraphKey::new(&repo, &options)?;
+    let row = load_graph(&app, &path, &options, &key)
+        .and_then(|graph| graph.iter().position(|row| row.commit.id == id));
+
+    Ok(ResolvedRevision { id, row })
+}
+
+/// Any revspec that ends up on a commit, full ids included.
+pub fn find_commit<'a>(repo: &'a Repository, revspec: &str) -> Result<Commit<'a>, git2::Error> {
+    repo.revparse_single(revspec)?.peel_to_commit()
+}
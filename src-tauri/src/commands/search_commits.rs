@@ -0,0 +1,326 @@
+use std::{
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+use crate::positioned_commit::{get_commit_ids, HistoryOptions};
+use crate::AppState;
+use git2::{Commit, Diff, Repository};
+use logging_timer::time;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
+
+use super::{
+    register_commit_stream, serializer::git_error::GitError, unregister_commit_stream,
+    BatchEmitter, CommitStreamSignal,
+};
+
+const BATCH_SIZE: usize = 200;
+// Longest a match waits to be emitted while the batch fills up.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Deserialize, PartialEq)]
+pub enum SearchField {
+    Message,
+    Author,
+    Committer,
+    Hash,
+    // Commits that change the amount of occurrences of the text (`git log -S`)
+    Pickaxe,
+    // Commits with an added or removed line matching the text as a regex (`git log -G`)
+    DiffLine,
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    text: String,
+    // A commit matches if any of the fields matches
+    fields: Vec<SearchField>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SearchMatch {
+    id: String,
+    row: usize,
+}
+
+/// Streams the commits matching `query` in batches to `search_commits-stream-{correlation_id}`,
+/// with their row on the graph built with the same `options`.
+/// Text fields are matched case-insensitively, content like git does.
+/// Same as `get_commits`, every batch has to be acknowledged through `ack_commits`, and
+/// the search can be stopped through `cancel_commits`.
+/// Returns the amount of batches emitted.
+#[time]
+#[tauri::command(async)]
+pub fn search_commits(
+    path: String,
+    correlation_id: String,
+    query: SearchQuery,
+    options: Option<HistoryOptions>,
+    window: Window,
+    state: State<AppState>,
+) -> Result<usize, GitError> {
+    let receiver = register_commit_stream(&state, &correlation_id);
+    let response_channel = format!("search_commits-stream-{correlation_id}");
+
+    let result = stream_matches(
+        &path,
+        &query,
+        options,
+        &window,
+        &response_channel,
+        &receiver,
+    );
+
+    unregister_commit_stream(&state, &correlation_id);
+
+    result
+}
+
+fn stream_matches(
+    path: &str,
+    query: &SearchQuery,
+    options: Option<HistoryOptions>,
+    window: &Window,
+    response_channel: &str,
+    receiver: &Receiver<CommitStreamSignal>,
+) -> Result<usize, GitError> {
+    let repo = Repository::open(path)?;
+    let options = options.unwrap_or_default();
+    let pattern = get_line_pattern(query)?;
+    let ids = get_commit_ids(&repo, &options)?;
+
+    let mut emitter = BatchEmitter::new(window, response_channel, receiver);
+    let mut batch = vec![];
+    let mut last_emit = Instant::now();
+    for (row, id) in ids.into_iter().enumerate() {
+        if emitter.is_cancelled() {
+            return Ok(emitter.emitted());
+        }
+
+        let matches = repo
+            .find_commit(id)
+            .map(|commit| commit_matches(&repo, &commit, query, pattern.as_ref()))
+            .unwrap_or(false);
+        if matches {
+            batch.push(SearchMatch {
+                id: id.to_string(),
+                row,
+            });
+        }
+
+        // Matches can be far apart, so they don't wait for a full batch for too long.
+        let full = batch.len() >= BATCH_SIZE;
+        let waited = !batch.is_empty() && last_emit.elapsed() >= FLUSH_INTERVAL;
+        if full || waited {
+            if !emitter.emit(&batch) {
+                return Ok(emitter.emitted());
+            }
+            batch.clear();
+            last_emit = Instant::now();
+        }
+    }
+
+    if !batch.is_empty() {
+        emitter.emit(&batch);
+    }
+    Ok(emitter.emitted())
+}
+
+// Compiled once for the whole search, an invalid pattern fails it before walking.
+fn get_line_pattern(query: &SearchQuery) -> Result<Option<Regex>, GitError> {
+    if !query.fields.contains(&SearchField::DiffLine) {
+        return Ok(None);
+    }
+    Regex::new(&query.text)
+        .map(Some)
+        .map_err(|e| GitError::Wrapped(e.to_string()))
+}
+
+fn commit_matches(
+    repo: &Repository,
+    commit: &Commit,
+    query: &SearchQuery,
+    pattern: Option<&Regex>,
+) -> bool {
+    let text = query.text.to_lowercase();
+    let contains = |value: Option<&str>| {
+        value
+            .map(|value| value.to_lowercase().contains(&text))
+            .unwrap_or(false)
+    };
+
+    query.fields.iter().any(|field| match field {
+        SearchField::Message => contains(commit.message()),
+        SearchField::Author => {
+            let author = commit.author();
+            contains(author.name()) || contains(author.email())
+        }
+        SearchField::Committer => {
+            let committer = commit.committer();
+            contains(committer.name()) || contains(committer.email())
+        }
+        SearchField::Hash => commit.id().to_string().starts_with(&text),
+        SearchField::Pickaxe => get_commit_diff(repo, commit)
+            .map(|diff| changes_occurrences(repo, &diff, query.text.as_bytes()))
+            .unwrap_or(false),
+        SearchField::DiffLine => pattern
+            .and_then(|pattern| {
+                get_commit_diff(repo, commit).map(|diff| has_changed_line(&diff, pattern))
+            })
+            .unwrap_or(false),
+    })
+}
+
+// Like `git log`, merge commits don't get their content searched.
+fn get_commit_diff<'a>(repo: &'a Repository, commit: &Commit) -> Option<Diff<'a>> {
+    if commit.parent_count() > 1 {
+        return None;
+    }
+
+    let commit_tree = commit.tree().ok()?;
+    let parent_tree = commit
+        .parents()
+        .next()
+        .and_then(|parent| parent.tree().ok());
+
+    repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit_tree), None)
+        .ok()
+}
+
+fn changes_occurrences(repo: &Repository, diff: &Diff, needle: &[u8]) -> bool {
+    let count = |id: git2::Oid| {
+        if id.is_zero() {
+            return 0;
+        }
+        repo.find_blob(id)
+            .map(|blob| count_occurrences(blob.content(), needle))
+            .unwrap_or(0)
+    };
+
+    diff.deltas()
+        .any(|delta| count(delta.old_file().id()) != count(delta.new_file().id()))
+}
+
+fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    haystack
+        .windows(needle.len())
+        .filter(|window| *window == needle)
+        .count()
+}
+
+fn has_changed_line(diff: &Diff, pattern: &Regex) -> bool {
+    let mut found = false;
+    // Returning false stops the iteration, which makes `foreach` return an error.
+    diff.foreach(
+        &mut |_, _| true,
+        None,
+        None,
+        Some(&mut |_, _, line| {
+            if matches!(line.origin(), '+' | '-') && pattern.is_match(line.content()) {
+                found = true;
+            }
+            !found
+        }),
+    )
+    .ok();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, Signature};
+    use tempfile::TempDir;
+
+    fn commit(repo: &Repository, message: &str, content: &str, parents: &[Oid]) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        builder.insert("file", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parents = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect::<Vec<_>>();
+        repo.commit(
+            None,
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn matches(repo: &Repository, id: Oid, text: &str, field: SearchField) -> bool {
+        let query = SearchQuery {
+            text: text.to_owned(),
+            fields: vec![field],
+        };
+        let pattern = get_line_pattern(&query).unwrap();
+        let commit = repo.find_commit(id).unwrap();
+        commit_matches(repo, &commit, &query, pattern.as_ref())
+    }
+
+    #[test]
+    fn matches_messages_and_hashes() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let id = commit(&repo, "Fix the Parser", "", &[]);
+
+        assert!(matches(&repo, id, "the parser", SearchField::Message));
+        assert!(!matches(&repo, id, "lexer", SearchField::Message));
+        assert!(matches(&repo, id, &id.to_string()[..7], SearchField::Hash));
+        assert!(!matches(&repo, id, "parser", SearchField::Hash));
+    }
+
+    #[test]
+    fn matches_changes_in_occurrences() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let added = commit(&repo, "add", "a\nneedle\n", &[]);
+        // Same amount of occurrences, only moved around
+        let moved = commit(&repo, "move", "needle\na\n", &[added]);
+        let removed = commit(&repo, "remove", "a\n", &[moved]);
+
+        assert!(matches(&repo, added, "needle", SearchField::Pickaxe));
+        assert!(!matches(&repo, moved, "needle", SearchField::Pickaxe));
+        assert!(matches(&repo, removed, "needle", SearchField::Pickaxe));
+    }
+
+    #[test]
+    fn matches_changed_lines_by_regex() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(&repo, "base", "fn main() {}\n", &[]);
+        let changed = commit(&repo, "change", "fn main() {}\nfn parse_42() {}\n", &[base]);
+
+        assert!(matches(&repo, changed, r"parse_\d+", SearchField::DiffLine));
+        // Unchanged lines don't count
+        assert!(!matches(&repo, changed, "main", SearchField::DiffLine));
+        // Moves are a change too, unlike with the pickaxe
+        let moved = commit(
+            &repo,
+            "move",
+            "fn parse_42() {}\nfn main() {}\n",
+            &[changed],
+        );
+        assert!(matches(&repo, moved, r"fn \w+", SearchField::DiffLine));
+        assert!(!matches(&repo, moved, "fn", SearchField::Pickaxe));
+    }
+
+    #[test]
+    fn fails_with_an_invalid_pattern() {
+        let query = SearchQuery {
+            text: "parse(".to_owned(),
+            fields: vec![SearchField::Message, SearchField::DiffLine],
+        };
+        assert!(get_line_pattern(&query).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use git2::{message_prettify, ErrorCode, ObjectType, Repository, Signature};
+use logging_timer::time;
+use serde::Serialize;
+use tauri::Window;
+
+use super::{find_commit, push_refspecs, HostKeyError, PushStatus};
+use crate::signature::sign;
+
+#[derive(Serialize)]
+pub enum TagError {
+    Read(String),
+    InvalidName,
+    AlreadyExists,
+    NotFound,
+    // The configured signing program failed
+    Signing(String),
+    // The remote refused to update the tag
+    Rejected(String),
+    HostKey(HostKeyError),
+}
+
+impl From<git2::Error> for TagError {
+    fn from(value: git2::Error) -> Self {
+        if let Some(error) = HostKeyError::from_error(&value) {
+            return TagError::HostKey(error);
+        }
+        match value.code() {
+            ErrorCode::Exists => TagError::AlreadyExists,
+            ErrorCode::InvalidSpec => TagError::InvalidName,
+            ErrorCode::NotFound => TagError::NotFound,
+            _ => TagError::Read(value.message().to_owned()),
+        }
+    }
+}
+
+/// Creates the tag `name` on `revspec`. Without a message it's a lightweight tag, otherwise
+/// it's annotated, and signed like `git tag -s` if `sign` is set.
+#[time]
+#[tauri::command(async)]
+pub fn create_tag(
+    path: String,
+    name: String,
+    revspec: String,
+    message: Option<String>,
+    sign: bool,
+) -> Result<(), TagError> {
+    let repo = Repository::open(path)?;
+    if !git2::Reference::is_valid_name(&format!("refs/tags/{}", name)) {
+        return Err(TagError::InvalidName);
+    }
+
+    let commit = find_commit(&repo, &revspec)?;
+    match (message, sign) {
+        (None, false) => {
+            repo.tag_lightweight(&name, commit.as_object(), false)?;
+        }
+        (message, false) => {
+            let message = message_prettify(message.unwrap_or_default(), None)?;
+            repo.tag(
+                &name,
+                commit.as_object(),
+                &repo.signature()?,
+                &message,
+                false,
+            )?;
+        }
+        (message, true) => {
+            let message = message_prettify(message.unwrap_or_default(), None)?;
+            create_signed_tag(&repo, &name, commit.id(), &message)?;
+        }
+    }
+
+    Ok(())
+}
+
+// libgit2 can't sign tags, so the object gets built here with the signature appended to
+// the message, which is what `git tag -s` writes.
+fn create_signed_tag(
+    repo: &Repository,
+    name: &str,
+    target: git2::Oid,
+    message: &str,
+) -> Result<(), TagError> {
+    let reference = format!("refs/tags/{}", name);
+    if repo.find_reference(&reference).is_ok() {
+        return Err(TagError::AlreadyExists);
+    }
+
+    let content = format!(
+        "object {}\ntype commit\ntag {}\ntagger {}\n\n{}",
+        target,
+        name,
+        format_signature(&repo.signature()?),
+        message
+    );
+    let signature = sign(repo, content.as_bytes()).map_err(TagError::Signing)?;
+
+    let id = repo.odb()?.write(
+        ObjectType::Tag,
+        format!("{}{}", content, signature).as_bytes(),
+    )?;
+    repo.reference(&reference, id, false, &format!("tag: tagging {}", target))?;
+
+    Ok(())
+}
+
+// `Name <email> 1234567890 +0100`, as it's written in the raw object
+fn format_signature(signature: &Signature) -> String {
+    let when = signature.when();
+    let offset = when.offset_minutes();
+    format!(
+        "{} <{}> {} {}{:02}{:02}",
+        String::from_utf8_lossy(signature.name_bytes()),
+        String::from_utf8_lossy(signature.email_bytes()),
+        when.seconds(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+#[time]
+#[tauri::command(async)]
+pub fn delete_tag(path: String, name: String) -> Result<(), TagError> {
+    let repo = Repository::open(path)?;
+    repo.tag_delete(&name)?;
+
+    Ok(())
+}
+
+#[time]
+#[tauri::command(async)]
+pub fn push_tag(
+    path: String,
+    remote: String,
+    name: String,
+    window: Window,
+) -> Result<(), TagError> {
+    let reference = format!("refs/tags/{}", name);
+    push_refspec(
+        path,
+        &remote,
+        &format!("{}:{}", reference, reference),
+        &window,
+    )
+}
+
+/// Deletes the tag on the remote only, the local one is kept.
+#[time]
+#[tauri::command(async)]
+pub fn delete_remote_tag(
+    path: String,
+    remote: String,
+    name: String,
+    window: Window,
+) -> Result<(), TagError> {
+    push_refspec(path, &remote, &format!(":refs/tags/{}", name), &window)
+}
+
+fn push_refspec(
+    path: String,
+    remote: &str,
+    refspec: &str,
+    window: &Window,
+) -> Result<(), TagError> {
+    let repo = Repository::open(path)?;
+    let mut remote = repo.find_remote(remote)?;
+
+    // The push itself succeeds even if the remote rejects the update, it gets reported per ref.
+    let rejected = push_refspecs(&repo, &mut remote, &[refspec.to_owned()], Some(window))?
+        .into_iter()
+        .find_map(|pushed| match pushed.status {
+            PushStatus::Rejected(reason) => Some(format!("{}: {}", pushed.reference, reason)),
+            _ => None,
+        });
+
+    match rejected {
+        Some(message) => Err(TagError::Rejected(message)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::{verify_tag, SignatureStatus};
+    use git2::Oid;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    // A repo with one commit on `main`, and an identity to tag with
+    fn setup() -> (TempDir, Repository, String, Oid) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+
+        let signature = repo.signature().unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "first", &tree, &[])
+            .unwrap();
+        drop(tree);
+
+        let path = repo.workdir().unwrap().to_str().unwrap().to_owned();
+        (dir, repo, path, commit)
+    }
+
+    #[test]
+    fn creates_lightweight_tags() {
+        let (_dir, repo, path, commit) = setup();
+
+        create_tag(path, "v1".to_owned(), "main".to_owned(), None, false)
+            .ok()
+            .unwrap();
+
+        let reference = repo.find_reference("refs/tags/v1").unwrap();
+        assert_eq!(reference.target(), Some(commit));
+    }
+
+    #[test]
+    fn creates_annotated_tags() {
+        let (_dir, repo, path, commit) = setup();
+
+        create_tag(
+            path,
+            "v1".to_owned(),
+            "HEAD".to_owned(),
+            Some("Release 1".to_owned()),
+            false,
+        )
+        .ok()
+        .unwrap();
+
+        let reference = repo.find_reference("refs/tags/v1").unwrap();
+        let tag = reference.peel_to_tag().unwrap();
+        assert_ne!(reference.target(), Some(commit));
+        assert_eq!(tag.target_id(), commit);
+        assert_eq!(reference.peel_to_commit().unwrap().id(), commit);
+        assert_eq!(tag.message(), Some("Release 1\n"));
+        assert_eq!(tag.tagger().unwrap().name(), Some("Test"));
+    }
+
+    #[test]
+    fn creates_signed_tags() {
+        let (dir, repo, path, commit) = setup();
+        let key = dir.path().join("key");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut config = repo.config().unwrap();
+        config.set_str("gpg.format", "ssh").unwrap();
+        config
+            .set_str("user.signingKey", key.to_str().unwrap())
+            .unwrap();
+
+        create_tag(
+            path,
+            "v1".to_owned(),
+            "main".to_owned(),
+            Some("Release 1".to_owned()),
+            true,
+        )
+        .ok()
+        .unwrap();
+
+        let tag = repo
+            .find_reference("refs/tags/v1")
+            .unwrap()
+            .peel_to_tag()
+            .unwrap();
+        assert_eq!(tag.target_id(), commit);
+        // Without allowed signers it can only be checked for integrity
+        assert!(matches!(
+            verify_tag(&repo, &tag),
+            SignatureStatus::Unverified(_)
+        ));
+    }
+
+    #[test]
+    fn refuses_invalid_tags() {
+        let (_dir, _repo, path, _) = setup();
+        create_tag(
+            path.clone(),
+            "v1".to_owned(),
+            "main".to_owned(),
+            None,
+            false,
+        )
+        .ok()
+        .unwrap();
+
+        let result = create_tag(
+            path.clone(),
+            "v1".to_owned(),
+            "main".to_owned(),
+            None,
+            false,
+        );
+        assert!(matches!(result, Err(TagError::AlreadyExists)));
+        let result = create_tag(
+            path.clone(),
+            "v1".to_owned(),
+            "main".to_owned(),
+            Some("Again".to_owned()),
+            false,
+        );
+        assert!(matches!(result, Err(TagError::AlreadyExists)));
+        let result = create_tag(
+            path.clone(),
+            "v..2".to_owned(),
+            "main".to_owned(),
+            None,
+            false,
+        );
+        assert!(matches!(result, Err(TagError::InvalidName)));
+        let result = create_tag(path, "v2".to_owned(), "nope".to_owned(), None, false);
+        assert!(matches!(result, Err(TagError::NotFound)));
+    }
+
+    #[test]
+    fn deletes_tags() {
+        let (_dir, repo, path, _) = setup();
+        create_tag(
+            path.clone(),
+            "v1".to_owned(),
+            "main".to_owned(),
+            None,
+            false,
+        )
+        .ok()
+        .unwrap();
+        create_tag(
+            path.clone(),
+            "v2".to_owned(),
+            "main".to_owned(),
+            Some("Release 2".to_owned()),
+            false,
+        )
+        .ok()
+        .unwrap();
+
+        delete_tag(path.clone(), "v1".to_owned()).ok().unwrap();
+        delete_tag(path.clone(), "v2".to_owned()).ok().unwrap();
+
+        assert!(repo.find_reference("refs/tags/v1").is_err());
+        assert!(repo.find_reference("refs/tags/v2").is_err());
+        let result = delete_tag(path, "v1".to_owned());
+        assert!(matches!(result, Err(TagError::NotFound)));
+    }
+}
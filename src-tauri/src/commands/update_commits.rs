@@ -0,0 +1,78 @@
+use crate::graph_cache::{get_mailmap_id, get_shallow_id, load_graph, save_graph, GraphKey};
+use crate::positioned_commit::{update_positioned_commits, HistoryOptions, PositionedCommit};
+use git2::Repository;
+use logging_timer::time;
+use serde::Serialize;
+
+use super::serializer::git_error::GitError;
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum CommitsUpdate {
+    // The graph can't be updated incrementally, it needs to be requested through `get_commits`
+    Reload,
+    Update {
+        tips: Vec<String>,
+        // Rows that go on top of the graph, replacing the first `replaced` ones.
+        rows: Vec<PositionedCommit>,
+        replaced: usize,
+    },
+}
+
+/// Updates the graph that was loaded for `previous_tips` after refs have moved
+/// (commit, fetch, checkout...), without positioning the whole history again.
+/// `previous_tips` are the ones `get_commits` returned, or the ones from the last update.
+#[time]
+#[tauri::command(async)]
+pub fn update_commits(
+    path: String,
+    previous_tips: Vec<String>,
+    options: Option<HistoryOptions>,
+    app: tauri::AppHandle,
+) -> Result<CommitsUpdate, GitError> {
+    let repo = Repository::open(&path)?;
+    let options = options.unwrap_or_default();
+
+    let mut previous_tips = previous_tips;
+    previous_tips.sort();
+    previous_tips.dedup();
+
+    // Only the compact layout depends on HEAD, and it's always positioned from scratch.
+    let mailmap = get_mailmap_id(&repo);
+    let shallow = get_shallow_id(&repo);
+    let previous_key = GraphKey {
+        tips: previous_tips.clone(),
+        head: None,
+        mailmap: mailmap.clone(),
+        shallow: shallow.clone(),
+    };
+    let previous = match load_graph(&app, &path, &options, &previous_key) {
+        Some(previous) => previous,
+        None => return Ok(CommitsUpdate::Reload),
+    };
+
+    let update = match update_positioned_commits(&repo, &options, &previous, &previous_tips)? {
+        Some(update) => update,
+        None => return Ok(CommitsUpdate::Reload),
+    };
+
+    let graph = update
+        .rows
+        .iter()
+        .cloned()
+        .chain(previous.into_iter().skip(update.replaced))
+        .collect();
+    let key = GraphKey {
+        tips: update.tips.clone(),
+        head: None,
+        mailmap,
+        shallow,
+    };
+    save_graph(&app, &path, &options, key, graph);
+
+    Ok(CommitsUpdate::Update {
+        tips: update.tips,
+        rows: update.rows,
+        replaced: update.replaced,
+    })
+}
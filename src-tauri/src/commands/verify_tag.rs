@@ -0,0 +1,22 @@
+use crate::signature::{verify_tag as verify_tag_signature, SignatureStatus};
+use git2::Repository;
+use logging_timer::time;
+
+use super::serializer::git_error::GitError;
+
+/// Checks the signature of the tag `name`, lightweight tags are never signed.
+#[time]
+#[tauri::command(async)]
+pub fn verify_tag(path: String, name: String) -> Result<SignatureStatus, GitError> {
+    let repo = Repository::open(path)?;
+
+    let reference = repo.find_reference(&format!("refs/tags/{name}"))?;
+    let tag = reference
+        .target()
+        .and_then(|target| repo.find_tag(target).ok());
+
+    Ok(match tag {
+        Some(tag) => verify_tag_signature(&repo, &tag),
+        None => SignatureStatus::Unsigned,
+    })
+}
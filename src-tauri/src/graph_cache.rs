@@ -0,0 +1,210 @@
+use git2::{ObjectType, Oid, Repository};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tauri::api::path;
+
+use crate::commands::serializer::git_error::GitError;
+use crate::positioned_commit::{get_layout_head, get_ref_tips, HistoryOptions, PositionedCommit};
+
+// Bump whenever the shape of `PositionedCommit` or `GraphKey` changes, so old caches get
+// discarded.
+const CACHE_VERSION: u32 = 8;
+const CACHE_DIR: &str = "graph_cache";
+// Graphs kept across every repo and set of options, the least recently saved go first.
+const MAX_CACHED_GRAPHS: usize = 20;
+
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Everything the positioned graph depends on other than the repo and the options.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphKey {
+    pub tips: Vec<String>,
+    // See `get_layout_head`
+    pub head: Option<String>,
+    // Names and emails in the graph are resolved through the mailmap
+    pub mailmap: Option<String>,
+    // Deepening or unshallowing a clone adds history below the same tips
+    pub shallow: Option<String>,
+}
+
+impl GraphKey {
+    pub fn new(repo: &Repository, options: &HistoryOptions) -> Result<Self, GitError> {
+        Ok(GraphKey {
+            tips: get_ref_tips(repo)?,
+            head: get_layout_head(repo, options).map(|oid| oid.to_string()),
+            mailmap: get_mailmap_id(repo),
+            shallow: get_shallow_id(repo),
+        })
+    }
+}
+
+// `.git/shallow` lists the grafted commits where a shallow clone's history stops.
+pub fn get_shallow_id(repo: &Repository) -> Option<String> {
+    Oid::hash_file(ObjectType::Blob, repo.path().join("shallow"))
+        .ok()
+        .map(|id| id.to_string())
+}
+
+// Same sources `Repository::mailmap` reads: `.mailmap` in the working dir, `mailmap.blob`
+// (`HEAD:.mailmap` in bare repos) and `mailmap.file`.
+pub fn get_mailmap_id(repo: &Repository) -> Option<String> {
+    let config = repo.config().ok();
+    let workdir_file = repo
+        .workdir()
+        .and_then(|workdir| Oid::hash_file(ObjectType::Blob, workdir.join(".mailmap")).ok());
+    let blob = config
+        .as_ref()
+        .and_then(|config| config.get_string("mailmap.blob").ok())
+        .or_else(|| repo.is_bare().then(|| "HEAD:.mailmap".to_owned()))
+        .and_then(|spec| repo.revparse_single(&spec).ok())
+        .map(|object| object.id());
+    let file = config
+        .as_ref()
+        .and_then(|config| config.get_path("mailmap.file").ok())
+        .and_then(|path| Oid::hash_file(ObjectType::Blob, path).ok());
+
+    let ids = [workdir_file, blob, file];
+    if ids.iter().all(|id| id.is_none()) {
+        return None;
+    }
+    Some(
+        ids.iter()
+            .map(|id| id.map(|id| id.to_string()).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+struct GraphCache {
+    version: u32,
+    key: GraphKey,
+    commits: Vec<PositionedCommit>,
+}
+
+// Each set of options produces a different graph, so they get their own file.
+fn get_cache_path(
+    app: &tauri::AppHandle,
+    repo_path: &str,
+    options: &HistoryOptions,
+) -> Option<PathBuf> {
+    let options = serde_json::to_string(options).ok()?;
+    path::app_local_data_dir(&app.config()).map(|path| {
+        path.join(CACHE_DIR).join(format!(
+            "{:?}",
+            md5::compute(format!("{repo_path}\n{options}"))
+        ))
+    })
+}
+
+/// Returns the positioned graph of `repo_path` if it was cached with exactly the same `key`.
+pub fn load_graph(
+    app: &tauri::AppHandle,
+    repo_path: &str,
+    options: &HistoryOptions,
+    key: &GraphKey,
+) -> Option<Vec<PositionedCommit>> {
+    let path = get_cache_path(app, repo_path, options)?;
+    if !path.exists() {
+        return None;
+    }
+
+    let cache = fs::read(&path)
+        .map_err(|e| error!("Error reading graph cache {:?} {:?}", path, e))
+        .ok()
+        .and_then(|content| {
+            serde_json::from_slice::<GraphCache>(&content)
+                .map_err(|e| error!("Error deserializing graph cache {:?} {:?}", path, e))
+                .ok()
+        })?;
+
+    if cache.version != CACHE_VERSION || cache.key != *key {
+        return None;
+    }
+    Some(cache.commits)
+}
+
+pub fn save_graph(
+    app: &tauri::AppHandle,
+    repo_path: &str,
+    options: &HistoryOptions,
+    key: GraphKey,
+    commits: Vec<PositionedCommit>,
+) {
+    let path = match get_cache_path(app, repo_path, options) {
+        Some(path) => path,
+        None => return,
+    };
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => return,
+    };
+    if let Err(e) = fs::create_dir_all(dir) {
+        error!("Error creating graph cache dir {:?}", e);
+        return;
+    }
+
+    let cache = GraphCache {
+        version: CACHE_VERSION,
+        key,
+        commits,
+    };
+    let content = match serde_json::to_vec(&cache) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Error serializing graph cache {:?}", e);
+            return;
+        }
+    };
+
+    // Written aside and then moved in place, so a reader never finds half a graph.
+    let temp_path = dir.join(format!(
+        ".tmp-{}-{}",
+        std::process::id(),
+        TEMP_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = fs::write(&temp_path, content).and_then(|_| fs::rename(&temp_path, &path));
+    if let Err(e) = result {
+        error!("Error saving graph cache {:?} {:?}", path, e);
+        fs::remove_file(&temp_path).ok();
+        return;
+    }
+
+    remove_old_graphs(dir);
+}
+
+fn remove_old_graphs(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Error reading graph cache dir {:?}", e);
+            return;
+        }
+    };
+    let mut graphs = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with(".tmp-"))
+        .filter_map(|entry| {
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect::<Vec<_>>();
+    if graphs.len() <= MAX_CACHED_GRAPHS {
+        return;
+    }
+
+    graphs.sort();
+    for (_, path) in graphs.iter().take(graphs.len() - MAX_CACHED_GRAPHS) {
+        if let Err(e) = fs::remove_file(path) {
+            error!("Error removing graph cache {:?} {:?}", path, e);
+        }
+    }
+}
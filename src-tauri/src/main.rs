@@ -4,21 +4,31 @@
 )]
 
 mod commands;
+mod graph_cache;
 mod http_server;
 mod positioned_commit;
 mod settings;
+mod signature;
+mod trailers;
 
 use crate::commands::{
-    checkout_commit, checkout_local, checkout_remote, commit, fetch, get_commit, get_commits,
-    get_diff, get_diff_settings, get_last_repo, get_refs, get_working_dir, open_repo,
-    set_diff_settings, stage, stage_hunk, stage_line, stop_watch_repo, unstage, unstage_hunk,
-    watch_repo,
+    ack_commits, answer_credentials, cancel_commits, cancel_fetch, checkout_commit, checkout_local,
+    checkout_remote, commit, create_branch, create_tag, delete_branch, delete_remote_branch,
+    delete_remote_tag, delete_tag, fetch, get_commit, get_commits, get_diff, get_diff_settings,
+    get_graph_layout, get_last_repo, get_refs, get_working_dir, open_repo, pull, push, push_tag,
+    rename_branch, resolve_revision, search_commits, set_diff_settings, set_graph_layout,
+    set_upstream, stage, stage_hunk, stage_line, stop_watch_repo, unstage, unstage_hunk,
+    update_commits, verify_tag, watch_repo, CommitStreams, CredentialResponse,
 };
 use crate::http_server::get_port;
 use env_logger::Env;
 use http_server::launch_server;
 use notify::RecommendedWatcher;
-use std::{sync::Mutex, thread};
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicBool, mpsc::Sender, Arc, Mutex},
+    thread,
+};
 use tauri::{CustomMenuItem, Manager, Menu, Submenu};
 
 extern crate rocket;
@@ -26,6 +36,11 @@ extern crate rocket;
 #[derive(Default)]
 pub struct AppState {
     watcher: Mutex<Option<RecommendedWatcher>>,
+    commit_streams: Mutex<CommitStreams>,
+    // Cancellation flags of the fetches running, by repo path
+    fetches: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    // Credential prompts waiting for the frontend to answer, by request id
+    credential_requests: Mutex<HashMap<usize, Sender<Option<CredentialResponse>>>>,
     port: u16,
 }
 
@@ -34,10 +49,20 @@ fn main() {
 
     let app = tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
+            ack_commits,
+            answer_credentials,
+            cancel_commits,
+            cancel_fetch,
             checkout_commit,
             checkout_local,
             checkout_remote,
             commit,
+            create_branch,
+            create_tag,
+            delete_branch,
+            delete_remote_branch,
+            delete_remote_tag,
+            delete_tag,
             fetch,
             get_commit,
             get_commits,
@@ -48,7 +73,16 @@ fn main() {
             get_working_dir,
             get_diff_settings,
             set_diff_settings,
+            get_graph_layout,
+            set_graph_layout,
             open_repo,
+            pull,
+            push,
+            push_tag,
+            rename_branch,
+            resolve_revision,
+            search_commits,
+            set_upstream,
             // something something
             stage,
             stage_hunk,
@@ -56,6 +90,8 @@ fn main() {
             stop_watch_repo,
             unstage,
             unstage_hunk,
+            update_commits,
+            verify_tag,
             watch_repo
         ])
         .menu(
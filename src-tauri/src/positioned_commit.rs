@@ -1,18 +1,26 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
 
-use git2::{Commit, Error, Oid, Repository, Revwalk, Signature, Sort};
+use git2::{Commit, Error, ErrorCode, Mailmap, Oid, Repository, Revwalk, Signature, Sort};
 use itertools::Itertools;
 use memoize::memoize;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::commands::serializer::git_error::GitError;
+use crate::signature::{get_signature_status, SignatureStatus};
+use crate::trailers::CommitTrailers;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignatureInfo {
     pub name: Option<String>,
     pub email: Option<String>,
     pub hash: Option<String>,
     pub time: i64,
+    // Minutes east of UTC where it was signed
+    pub offset: i32,
 }
 
 #[memoize]
@@ -27,11 +35,12 @@ impl SignatureInfo {
             email: signature.email().map(|v| v.to_owned()),
             hash: signature.email().map(|v| get_md5_string(v.to_owned())),
             time: signature.when().seconds(),
+            offset: signature.when().offset_minutes(),
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CommitInfo {
     pub id: String,
     pub summary: Option<String>,
@@ -40,56 +49,165 @@ pub struct CommitInfo {
     pub parents: Vec<String>,
     pub author: SignatureInfo,
     pub committer: SignatureInfo,
+    // Only whether it's signed, `get_commit` verifies it
+    pub signature: SignatureStatus,
+    pub trailers: CommitTrailers,
 }
 
 impl CommitInfo {
-    pub fn new(commit: &Commit) -> Self {
+    /// Identities go through `mailmap`, so each person shows up with their canonical
+    /// name and email.
+    pub fn new(commit: &Commit, mailmap: &Mailmap) -> Self {
         CommitInfo {
             id: commit.id().to_string(),
             summary: commit.summary().map(|v| v.to_owned()),
             body: commit.body().map(|v| v.to_owned()),
             time: commit.time().seconds(),
             parents: commit.parent_ids().map(|id| id.to_string()).collect_vec(),
-            author: SignatureInfo::new(&commit.author()),
-            committer: SignatureInfo::new(&commit.committer()),
+            author: SignatureInfo::new(
+                &commit
+                    .author_with_mailmap(mailmap)
+                    .unwrap_or_else(|_| commit.author()),
+            ),
+            committer: SignatureInfo::new(
+                &commit
+                    .committer_with_mailmap(mailmap)
+                    .unwrap_or_else(|_| commit.committer()),
+            ),
+            signature: get_signature_status(commit),
+            trailers: CommitTrailers::new(commit, mailmap),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload")]
 pub enum BranchPath {
-    Base(usize),   // top -> commit
-    Parent(usize), // commit -> bottom
-    Follow(usize), // top -> bottom
-                   // Line(usize, usize), // top -> bottom
+    Base(Lane),   // top -> commit
+    Parent(Lane), // commit -> bottom
+    Follow(Lane), // top -> bottom
+                  // Line(usize, usize), // top -> bottom
 }
 
-#[derive(Debug, Serialize)]
+/// A column of the graph, along with the identity of the line going through it.
+/// `id` comes from the first commit the line was opened for, so it stays the same
+/// for the whole branch (and across reloads), and can be used to pick its colour.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lane {
+    pub position: usize,
+    pub id: u32,
+}
+
+impl Lane {
+    fn new(position: usize, id: u32) -> Self {
+        Lane { position, id }
+    }
+}
+
+fn lane_id(oid: Oid) -> u32 {
+    let bytes = oid.as_bytes();
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum NodeType {
+    Commit,
+    Stash(usize), // index on the stash list, stash@{n}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PositionedCommit {
     pub commit: CommitInfo,
+    pub node: NodeType,
     pub descendants: Vec<String>,
     pub position: usize,
+    pub lane: u32,
     pub paths: Vec<BranchPath>,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum HistoryOrder {
+    // Parents after children, otherwise by commit date (`git log --date-order`)
+    #[default]
+    Date,
+    // Parents after children, otherwise by author date (`git log --author-date-order`)
+    AuthorDate,
+    // Parents after children, keeping branches together (`git log --topo-order`)
+    Topological,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum HistoryLayout {
+    // New branches and merged parents take the first free column
+    #[default]
+    Spread,
+    // The checked out branch stays on the first column, and parents join the lane
+    // that's already waiting for them on their left, to keep the graph narrow.
+    Compact,
+}
+
+/// Which commits go into the graph and in which order.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryOptions {
+    #[serde(default)]
+    pub first_parent: bool,
+    #[serde(default)]
+    pub order: HistoryOrder,
+    // Only walk from these refs (full names or shorthands) instead of every branch and tag.
+    #[serde(default)]
+    pub refs: Option<Vec<String>>,
+    // Only commits that changed this file or directory (`git log -- <path>`).
+    #[serde(default)]
+    pub path_filter: Option<String>,
+    #[serde(default)]
+    pub layout: HistoryLayout,
+}
+
+/// Lane state carried from one row of the graph to the next.
+/// It can be kept around to continue positioning commits later on.
+#[derive(Clone, Default, PartialEq)]
+pub struct PositionerState {
+    // Commit expected next on each column, with the id of the lane it belongs to.
+    branches: Vec<Option<(Oid, u32)>>,
+    descendants: HashMap<String, Vec<String>>,
+    // Merged parents don't get a lane when only following the first parent.
+    first_parent: bool,
+    // Parents to use instead of the real ones, when some commits were filtered out.
+    rewritten_parents: Option<Arc<HashMap<Oid, Vec<Oid>>>>,
+    // Stashes only hang from their base commit, their index and untracked commits are hidden.
+    stashes: Arc<HashMap<Oid, usize>>,
+    layout: HistoryLayout,
+    // Commit that goes on the first column when it comes up, which is kept free until then.
+    pinned: Option<Oid>,
+}
+
+/// Layout of a single row of the graph.
+struct Row {
+    position: usize,
+    lane: u32,
+    paths: Vec<BranchPath>,
+    descendants: Vec<String>,
+    parent_ids: Vec<Oid>,
+}
+
 struct CommitPositioner<'a, I>
 where
     I: Iterator<Item = Commit<'a>>,
 {
-    branches: Vec<Option<Oid>>,
-    descendants: HashMap<String, Vec<String>>,
+    state: PositionerState,
+    mailmap: Mailmap,
     underlying: I,
 }
 
 trait PositionCommit<'a>: Iterator<Item = Commit<'a>> {
-    fn position_commit(self) -> CommitPositioner<'a, Self>
+    fn position_commit(self, state: PositionerState, mailmap: Mailmap) -> CommitPositioner<'a, Self>
     where
         Self: Sized,
     {
         CommitPositioner {
-            branches: vec![],
-            descendants: HashMap::new(),
+            state,
+            mailmap,
             underlying: self,
         }
     }
@@ -104,12 +222,91 @@ where
     type Item = PositionedCommit;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let r = self.underlying.next();
-        if let None = r {
-            return None;
+        let commit = self.underlying.next()?;
+        Some(self.state.position(&commit, &self.mailmap))
+    }
+}
+
+impl PositionerState {
+    pub fn new(options: &HistoryOptions) -> Self {
+        PositionerState {
+            first_parent: options.first_parent,
+            layout: options.layout.clone(),
+            ..Default::default()
         }
-        let commit = r.unwrap();
+    }
+
+    fn parent_ids(&self, commit: &Commit) -> Vec<Oid> {
+        let first_parent = self.first_parent || self.stashes.contains_key(&commit.id());
+        match &self.rewritten_parents {
+            Some(rewritten) => rewritten.get(&commit.id()).cloned().unwrap_or_default(),
+            None => commit
+                .parent_ids()
+                .take(if first_parent { 1 } else { usize::MAX })
+                .collect_vec(),
+        }
+    }
 
+    fn free_position(&self) -> usize {
+        let first = if self.pinned.is_some() { 1 } else { 0 };
+        self.branches
+            .iter()
+            .enumerate()
+            .skip(first)
+            .find(|(_, v)| v.is_none())
+            .map(|(pos, _)| pos)
+            .unwrap_or(self.branches.len().max(first))
+    }
+
+    /// Column and lane of the branch that's waiting for `id`, if any.
+    fn find_branch(&self, id: Oid) -> Option<(usize, u32)> {
+        self.branches
+            .iter()
+            .enumerate()
+            .find_map(|(position, content)| {
+                content.and_then(|(oid, lane)| {
+                    if id.eq(&oid) {
+                        Some((position, lane))
+                    } else {
+                        None
+                    }
+                })
+            })
+    }
+
+    fn set_branch(&mut self, position: usize, content: Option<(Oid, u32)>) {
+        if position >= self.branches.len() {
+            self.branches.resize(position + 1, None);
+        }
+        self.branches[position] = content;
+    }
+
+    pub fn position(&mut self, commit: &Commit, mailmap: &Mailmap) -> PositionedCommit {
+        let row = self.advance(commit);
+
+        let node = match self.stashes.get(&commit.id()) {
+            Some(index) => NodeType::Stash(*index),
+            None => NodeType::Commit,
+        };
+
+        let mut commit_info = CommitInfo::new(commit, mailmap);
+        if self.rewritten_parents.is_some() || self.stashes.contains_key(&commit.id()) {
+            commit_info.parents = row.parent_ids.iter().map(|id| id.to_string()).collect_vec();
+        }
+
+        PositionedCommit {
+            commit: commit_info,
+            node,
+            position: row.position,
+            lane: row.lane,
+            descendants: row.descendants,
+            paths: row.paths,
+        }
+    }
+
+    /// Lays out the row of `commit` and moves the lane state past it, without
+    /// reading anything else from the commit.
+    fn advance(&mut self, commit: &Commit) -> Row {
         // Step 1. set position and color of the commit + top paths (BranchPath::Base)
         let matching_branches = self
             .branches
@@ -117,9 +314,9 @@ where
             .map(|x| x.to_owned())
             .enumerate()
             .filter_map(|(i, content)| {
-                content.and_then(|id| {
+                content.and_then(|(id, lane)| {
                     if commit.id().eq(&id) {
-                        Some((i, id))
+                        Some((i, lane))
                     } else {
                         None
                     }
@@ -127,24 +324,25 @@ where
             })
             .collect_vec();
 
-        let (position, top_paths) = if matching_branches.len() == 0 {
+        let pinned = self.pinned == Some(commit.id());
+        if pinned {
+            self.pinned = None;
+        }
+
+        let (position, lane, top_paths) = if matching_branches.len() == 0 {
             // It's a new branch
-            let position = self
-                .branches
-                .iter()
-                .find_position(|v| v.is_none())
-                .map(|(pos, _)| pos)
-                .unwrap_or(self.branches.len());
-            (position, vec![])
+            let position = if pinned { 0 } else { self.free_position() };
+            (position, lane_id(commit.id()), vec![])
         } else {
             // This commit is a base of all `matching_branches`
             // It will take the position and color of the first one (left-most)
-            let (position, _) = matching_branches[0];
+            let (position, lane) = matching_branches[0];
+            let position = if pinned { 0 } else { position };
             let top_paths = matching_branches
                 .iter()
-                .map(|(position, _)| BranchPath::Base(*position))
+                .map(|(position, lane)| BranchPath::Base(Lane::new(*position, *lane)))
                 .collect_vec();
-            (position, top_paths)
+            (position, lane, top_paths)
         };
 
         // Step 2. Follow through untouched branches
@@ -154,11 +352,11 @@ where
             .map(|x| x.to_owned())
             .enumerate()
             .filter_map(|(i, content)| {
-                content.and_then(|id| {
+                content.and_then(|(id, lane)| {
                     if commit.id().eq(&id) {
                         None
                     } else {
-                        Some(BranchPath::Follow(i))
+                        Some(BranchPath::Follow(Lane::new(i, lane)))
                     }
                 })
             })
@@ -167,7 +365,15 @@ where
         self.branches = self
             .branches
             .iter()
-            .map(|content| content.and_then(|id| if commit.id().eq(&id) { None } else { Some(id) }))
+            .map(|content| {
+                content.and_then(|(id, lane)| {
+                    if commit.id().eq(&id) {
+                        None
+                    } else {
+                        Some((id, lane))
+                    }
+                })
+            })
             .collect();
 
         // Step 3. Wire everything up
@@ -178,45 +384,42 @@ where
             .collect_vec();
 
         // Step 4. Add this commit's legacy
-        commit.parent_ids().enumerate().for_each(|(i, parent_id)| {
+        let parent_ids = self.parent_ids(commit);
+        parent_ids.iter().enumerate().for_each(|(i, &parent_id)| {
             if i == 0 {
-                if position == self.branches.len() {
-                    self.branches.push(Some(parent_id))
-                } else {
-                    self.branches[position] = Some(parent_id);
-                }
-                paths.push(BranchPath::Parent(position));
+                // The first parent carries on the same lane
+                let (position, lane) = match self.layout {
+                    HistoryLayout::Compact => self
+                        .find_branch(parent_id)
+                        .filter(|(existing, _)| *existing < position)
+                        .unwrap_or_else(|| {
+                            if self.pinned == Some(parent_id) {
+                                (0, lane)
+                            } else {
+                                (position, lane)
+                            }
+                        }),
+                    HistoryLayout::Spread => (position, lane),
+                };
+                self.set_branch(position, Some((parent_id, lane)));
+                paths.push(BranchPath::Parent(Lane::new(position, lane)));
             } else {
                 // We can try and split it from an existing path if it's already there
-                let existing = self
-                    .branches
-                    .iter()
-                    .find_position(|content| {
-                        content
-                            .and_then(|id| Some(parent_id.eq(&id)))
-                            .unwrap_or(false)
-                    })
-                    .map(|(position, _)| position);
-                let position = existing.unwrap_or_else(|| {
-                    let position = self
-                        .branches
-                        .iter()
-                        .find_position(|v| v.is_none())
-                        .map(|(pos, _)| pos)
-                        .unwrap_or(self.branches.len());
-                    position
+                let (position, lane) = self.find_branch(parent_id).unwrap_or_else(|| {
+                    let position = if self.pinned == Some(parent_id) {
+                        0
+                    } else {
+                        self.free_position()
+                    };
+                    (position, lane_id(parent_id))
                 });
 
-                if position == self.branches.len() {
-                    self.branches.push(Some(parent_id))
-                } else {
-                    self.branches[position] = Some(parent_id);
-                }
-                paths.push(BranchPath::Parent(position));
+                self.set_branch(position, Some((parent_id, lane)));
+                paths.push(BranchPath::Parent(Lane::new(position, lane)));
             }
         });
 
-        commit.parent_ids().for_each(|parent| {
+        parent_ids.iter().for_each(|parent| {
             self.descendants
                 .entry(parent.to_string())
                 .or_insert_with(|| vec![])
@@ -228,34 +431,730 @@ where
             .remove(&commit.id().to_string())
             .unwrap_or(vec![]);
 
-        Some(PositionedCommit {
-            commit: CommitInfo::new(&commit),
+        Row {
             position,
-            descendants,
+            lane,
             paths,
-        })
+            descendants,
+            parent_ids,
+        }
+    }
+
+    /// Moves the state past a row that was already positioned, without having to
+    /// look the commit up again.
+    fn replay(&mut self, row: &PositionedCommit) -> Result<(), Error> {
+        let id = Oid::from_str(&row.commit.id)?;
+        if self.pinned == Some(id) {
+            self.pinned = None;
+        }
+
+        self.branches = self
+            .branches
+            .iter()
+            .map(|content| {
+                content.and_then(|(oid, lane)| if id.eq(&oid) { None } else { Some((oid, lane)) })
+            })
+            .collect();
+
+        let parent_lanes = row.paths.iter().filter_map(|path| match path {
+            BranchPath::Parent(lane) => Some(lane),
+            _ => None,
+        });
+        for (lane, parent) in parent_lanes.zip(row.commit.parents.iter()) {
+            let parent_id = Oid::from_str(parent)?;
+            self.set_branch(lane.position, Some((parent_id, lane.id)));
+            self.descendants
+                .entry(parent.clone())
+                .or_insert_with(|| vec![])
+                .push(row.commit.id.clone());
+        }
+        self.descendants.remove(&row.commit.id);
+
+        Ok(())
     }
 }
 
 pub fn get_positioned_commits<'a>(
     repo: &'a Repository,
+    options: &HistoryOptions,
 ) -> Result<impl Iterator<Item = PositionedCommit> + 'a, GitError> {
-    Ok(get_revwalk(&repo)?
-        .filter_map(|oid| oid.ok().and_then(|oid| repo.find_commit(oid).ok()))
-        .position_commit())
+    let walk = walk_graph(repo, options)?;
+    let mailmap = repo.mailmap()?;
+    Ok(walk
+        .ids
+        .into_iter()
+        .filter_map(|oid| repo.find_commit(oid).ok())
+        .position_commit(walk.state, mailmap))
+}
+
+/// Result of laying out new commits on top of a previously positioned graph.
+pub struct GraphUpdate {
+    pub tips: Vec<String>,
+    /// New rows for the top of the graph, replacing the first `replaced` old rows.
+    pub rows: Vec<PositionedCommit>,
+    pub replaced: usize,
+}
+
+/// Positions the commits that aren't reachable from `previous_tips` on top of `previous`,
+/// the graph that was positioned for those tips.
+/// New commits can sort below old rows (e.g. a fetched branch with older dates), so they
+/// are merged in the order of the current walk. Old rows get positioned again only until
+/// every new commit is in and the lane state converges with the one they had, so only
+/// the walk itself depends on the size of the history.
+/// Returns `None` if some commit might have disappeared (e.g. amend or a deleted branch),
+/// if old rows would change their order, or when filtering by path, which needs the whole
+/// graph to be positioned again.
+/// The compact layout also needs it, as its first column depends on HEAD.
+pub fn update_positioned_commits(
+    repo: &Repository,
+    options: &HistoryOptions,
+    previous: &[PositionedCommit],
+    previous_tips: &[String],
+) -> Result<Option<GraphUpdate>, GitError> {
+    // Rewritten parents of new commits can point anywhere in the old graph.
+    if options.path_filter.is_some() || options.layout == HistoryLayout::Compact {
+        return Ok(None);
+    }
+
+    let tips = get_ref_tips(repo)?;
+    let previous_tips = previous_tips
+        .iter()
+        .map(|tip| Oid::from_str(tip))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Every previous tip must still be reachable, otherwise some rows would have to go.
+    let current_tips = tips
+        .iter()
+        .map(|tip| Oid::from_str(tip))
+        .collect::<Result<Vec<_>, _>>()?;
+    for tip in previous_tips.iter() {
+        let reachable = current_tips.contains(tip)
+            || current_tips
+                .iter()
+                .any(|current| repo.graph_descendant_of(*current, *tip).unwrap_or(false));
+        if !reachable {
+            return Ok(None);
+        }
+    }
+
+    let previous_ids = previous
+        .iter()
+        .map(|row| Oid::from_str(&row.commit.id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let previous_set: HashSet<Oid> = previous_ids.iter().copied().collect();
+
+    let walk = walk_graph(repo, options)?;
+    let mut new_left = walk
+        .ids
+        .iter()
+        .filter(|oid| !previous_set.contains(oid))
+        .count();
+
+    let mailmap = repo.mailmap()?;
+    let mut previous_state = walk.state.clone();
+    let mut state = walk.state;
+    let mut rows = vec![];
+    let mut replaced = 0;
+    let mut next = 0;
+    while next < walk.ids.len() && (new_left > 0 || state != previous_state) {
+        let oid = walk.ids[next];
+        if previous_set.contains(&oid) {
+            // Old rows have to come up in the order they were positioned in.
+            if previous_ids.get(replaced) != Some(&oid) {
+                return Ok(None);
+            }
+            previous_state.replay(&previous[replaced])?;
+            replaced += 1;
+        } else {
+            new_left -= 1;
+        }
+
+        let commit = repo.find_commit(oid)?;
+        rows.push(state.position(&commit, &mailmap));
+        next += 1;
+    }
+
+    // The rest of the walk are the old rows that are kept as they were.
+    if walk.ids[next..] != previous_ids[replaced..] {
+        return Ok(None);
+    }
+
+    Ok(Some(GraphUpdate {
+        tips,
+        rows,
+        replaced,
+    }))
+}
+
+/// Ids of every commit the revwalk starts from, sorted. Annotated tags are peeled, same
+/// as in `get_refs`. If these don't change, neither does the positioned graph.
+pub fn get_ref_tips(repo: &Repository) -> Result<Vec<String>, GitError> {
+    let mut tips = repo
+        .references()?
+        .filter_map(|reference| reference.ok())
+        .filter(|reference| reference.is_branch() || reference.is_remote() || reference.is_tag())
+        // Tags of trees or blobs aren't in the graph
+        .filter_map(|reference| reference.peel_to_commit().ok())
+        .map(|commit| commit.id().to_string())
+        .collect_vec();
+
+    if let Some(head) = repo.head().ok().and_then(|head| head.target()) {
+        tips.push(head.to_string());
+    }
+
+    tips.extend(get_stash_ids(repo)?.iter().map(|oid| oid.to_string()));
+
+    tips.sort();
+    tips.dedup();
+    Ok(tips)
+}
+
+/// Commit the layout is built around, which only the compact layout has.
+/// A graph positioned for a different one would look different.
+pub fn get_layout_head(repo: &Repository, options: &HistoryOptions) -> Option<Oid> {
+    match options.layout {
+        HistoryLayout::Compact => repo.head().ok().and_then(|head| head.target()),
+        HistoryLayout::Spread => None,
+    }
+}
+
+/// Stash commits from the stash reflog, stash@{0} first.
+pub fn get_stash_ids(repo: &Repository) -> Result<Vec<Oid>, Error> {
+    if repo.find_reference("refs/stash").is_err() {
+        return Ok(vec![]);
+    }
+    Ok(repo
+        .reflog("refs/stash")?
+        .iter()
+        .map(|entry| entry.id_new())
+        .collect_vec())
+}
+
+/// Ids of the commits in the graph, in the same order as `get_positioned_commits`.
+pub fn get_commit_ids(repo: &Repository, options: &HistoryOptions) -> Result<Vec<Oid>, GitError> {
+    Ok(walk_graph(repo, options)?.ids)
 }
 
-fn get_revwalk(repo: &Repository) -> Result<Revwalk, Error> {
+/// Commits in the graph, in the order they have to be positioned, and the lane state
+/// to start positioning them with.
+struct GraphWalk {
+    ids: Vec<Oid>,
+    state: PositionerState,
+}
+
+fn walk_graph(repo: &Repository, options: &HistoryOptions) -> Result<GraphWalk, GitError> {
+    let mut walker = get_revwalk(repo, options)?;
+
+    let mut stashes = HashMap::new();
+    let mut stash_internals = HashSet::new();
+    if options.refs.is_none() {
+        for (index, oid) in get_stash_ids(repo)?.into_iter().enumerate() {
+            let stash = repo.find_commit(oid)?;
+            // The index commit and the untracked files commit
+            stash_internals.extend(stash.parent_ids().skip(1));
+            stashes.insert(oid, index);
+            walker.push(oid)?;
+        }
+    }
+
+    let ids = walker
+        .filter(|oid| {
+            oid.as_ref()
+                .map(|oid| !stash_internals.contains(oid))
+                .unwrap_or(true)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let ids = match options.order {
+        HistoryOrder::AuthorDate => sort_by_author_date(repo, ids, options.first_parent)?,
+        _ => ids,
+    };
+
+    let mut state = PositionerState::new(options);
+    state.stashes = Arc::new(stashes);
+    let ids = match &options.path_filter {
+        Some(path) => {
+            let (ids, rewritten_parents) =
+                simplify_by_path(repo, ids, Path::new(path), options.first_parent)?;
+            state.rewritten_parents = Some(Arc::new(rewritten_parents));
+            ids
+        }
+        None => ids,
+    };
+
+    if let Some(head) = get_layout_head(repo, options) {
+        if ids.contains(&head) {
+            state.pinned = Some(head);
+        }
+    }
+
+    Ok(GraphWalk { ids, state })
+}
+
+/// Keeps only the commits that changed `path`, and rewrites their parents to the closest
+/// ancestors that were kept, so lanes stay connected.
+/// Like `git log`, a merge that didn't change `path` compared to one of its parents only
+/// follows that parent.
+fn simplify_by_path(
+    repo: &Repository,
+    ids: Vec<Oid>,
+    path: &Path,
+    first_parent: bool,
+) -> Result<(Vec<Oid>, HashMap<Oid, Vec<Oid>>), GitError> {
+    let included: HashSet<Oid> = ids.iter().copied().collect();
+    let mut entries: HashMap<Oid, Option<Oid>> = HashMap::new();
+    let mut entry_at = |commit: &Commit| -> Option<Oid> {
+        *entries.entry(commit.id()).or_insert_with(|| {
+            commit
+                .tree()
+                .ok()
+                .and_then(|tree| tree.get_path(path).ok())
+                .map(|entry| entry.id())
+        })
+    };
+
+    // Closest kept commits reachable from each commit (itself if it's kept).
+    let mut closest: HashMap<Oid, Vec<Oid>> = HashMap::new();
+    let mut rewritten_parents = HashMap::new();
+
+    // Parents go before children
+    for id in ids.iter().rev() {
+        let commit = repo.find_commit(*id)?;
+        let entry = entry_at(&commit);
+        let parents = commit
+            .parents()
+            .take(if first_parent { 1 } else { usize::MAX })
+            .collect_vec();
+
+        let same_parent = parents
+            .iter()
+            .find(|parent| entry_at(parent) == entry)
+            .map(|parent| parent.id());
+        let changed = match same_parent {
+            Some(_) => false,
+            None => !parents.is_empty() || entry.is_some(),
+        };
+
+        if changed {
+            let parents = parents
+                .iter()
+                .filter(|parent| included.contains(&parent.id()))
+                .flat_map(|parent| closest.get(&parent.id()).cloned().unwrap_or_default())
+                .unique()
+                .collect_vec();
+            rewritten_parents.insert(*id, parents);
+            closest.insert(*id, vec![*id]);
+        } else if let Some(parent) = same_parent.filter(|parent| included.contains(parent)) {
+            let parent_closest = closest.get(&parent).cloned().unwrap_or_default();
+            closest.insert(*id, parent_closest);
+        }
+    }
+
+    let ids = ids
+        .into_iter()
+        .filter(|id| rewritten_parents.contains_key(id))
+        .collect_vec();
+
+    Ok((ids, rewritten_parents))
+}
+
+/// libgit2 can only sort by commit date, so this does the topological sort
+/// picking the most recently authored commit among the ones that are ready.
+fn sort_by_author_date(
+    repo: &Repository,
+    ids: Vec<Oid>,
+    first_parent: bool,
+) -> Result<Vec<Oid>, GitError> {
+    let included: HashSet<Oid> = ids.iter().copied().collect();
+    let mut parents: HashMap<Oid, Vec<Oid>> = HashMap::new();
+    let mut author_time: HashMap<Oid, i64> = HashMap::new();
+    let mut pending_children: HashMap<Oid, usize> = HashMap::new();
+
+    for id in ids.iter() {
+        let commit = repo.find_commit(*id)?;
+        let commit_parents = commit
+            .parent_ids()
+            .take(if first_parent { 1 } else { usize::MAX })
+            .filter(|parent| included.contains(parent))
+            .collect_vec();
+        for parent in commit_parents.iter() {
+            *pending_children.entry(*parent).or_insert(0) += 1;
+        }
+        author_time.insert(*id, commit.author().when().seconds());
+        parents.insert(*id, commit_parents);
+    }
+
+    // Ties keep the original order, which is the reverse of the index.
+    let mut ready = ids
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| !pending_children.contains_key(id))
+        .map(|(i, id)| (author_time[id], usize::MAX - i, *id))
+        .collect::<BinaryHeap<_>>();
+    let index: HashMap<Oid, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+    let mut result = Vec::with_capacity(ids.len());
+    while let Some((_, _, id)) = ready.pop() {
+        result.push(id);
+        for parent in parents.get(&id).into_iter().flatten() {
+            // Parents outside of the walk (hidden, or not followed) aren't counted
+            let (children, time, i) = match (
+                pending_children.get_mut(parent),
+                author_time.get(parent),
+                index.get(parent),
+            ) {
+                (Some(children), Some(time), Some(i)) => (children, *time, *i),
+                _ => continue,
+            };
+            *children -= 1;
+            if *children == 0 {
+                ready.push((time, usize::MAX - i, *parent));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn get_revwalk<'a>(repo: &'a Repository, options: &HistoryOptions) -> Result<Revwalk<'a>, Error> {
     let mut walker = repo.revwalk()?;
-    walker.set_sorting(Sort::TOPOLOGICAL.union(Sort::TIME))?;
+    walker.set_sorting(match options.order {
+        HistoryOrder::Date => Sort::TOPOLOGICAL.union(Sort::TIME),
+        HistoryOrder::AuthorDate | HistoryOrder::Topological => Sort::TOPOLOGICAL,
+    })?;
+    if options.first_parent {
+        walker.simplify_first_parent()?;
+    }
 
-    // walker.push_glob("*")?; // All (also stashes)
+    if let Some(refs) = &options.refs {
+        for name in refs.iter() {
+            let reference = repo.resolve_reference_from_short_name(name)?;
+            walker.push(reference.peel_to_commit()?.id())?;
+        }
+        return Ok(walker);
+    }
+
+    // Stashes get pushed separately, their internal commits must be left out.
     walker.push_glob("refs/heads")?; // Local branches
     walker.push_glob("refs/remotes")?; // Remote branches
     walker.push_glob("refs/tags")?; // Tags
 
     // Add the head in case it's detached
-    walker.push_head()?;
+    match repo.head() {
+        Ok(_) => walker.push_head()?,
+        // Empty repo or orphan branch, it doesn't point to any commit yet
+        Err(e) if e.code() == ErrorCode::UnbornBranch => {}
+        Err(e) => return Err(e),
+    }
 
     Ok(walker)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Time;
+    use tempfile::TempDir;
+
+    fn init() -> (TempDir, Repository) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    // A commit authored at `time` with only `files` in its tree
+    fn commit(repo: &Repository, files: &[(&str, &str)], parents: &[Oid], time: i64) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::new("Test", "test@example.com", &Time::new(time, 0)).unwrap();
+        let parents = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect_vec();
+        repo.commit(
+            None,
+            &signature,
+            &signature,
+            "commit",
+            &tree,
+            &parents.iter().collect_vec(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sorts_by_author_date() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 1);
+        let b = commit(&repo, &[], &[a], 3);
+        let c = commit(&repo, &[], &[a], 2);
+        let merge = commit(&repo, &[], &[c, b], 4);
+
+        let sorted = sort_by_author_date(&repo, vec![merge, c, b, a], false).unwrap();
+
+        assert_eq!(sorted, vec![merge, b, c, a]);
+    }
+
+    #[test]
+    fn keeps_children_before_parents() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 10);
+        // Authored before its parent, e.g. after a rebase
+        let b = commit(&repo, &[], &[a], 5);
+        let c = commit(&repo, &[], &[a], 7);
+
+        let sorted = sort_by_author_date(&repo, vec![c, b, a], false).unwrap();
+
+        assert_eq!(sorted, vec![c, b, a]);
+    }
+
+    #[test]
+    fn sorts_with_parents_left_out() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 1);
+        let b = commit(&repo, &[], &[], 2);
+        let c = commit(&repo, &[], &[a], 3);
+        let merge = commit(&repo, &[], &[c, b], 4);
+
+        // `a` is hidden, and `b` isn't followed
+        let sorted = sort_by_author_date(&repo, vec![merge, c, b], true).unwrap();
+
+        assert_eq!(sorted, vec![merge, c, b]);
+    }
+
+    #[test]
+    fn simplifies_by_path() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[("file", "1")], &[], 1);
+        let b = commit(&repo, &[("file", "1"), ("other", "1")], &[a], 2);
+        let c = commit(&repo, &[("file", "2"), ("other", "1")], &[b], 3);
+
+        let (ids, parents) =
+            simplify_by_path(&repo, vec![c, b, a], Path::new("file"), false).unwrap();
+
+        assert_eq!(ids, vec![c, a]);
+        assert_eq!(parents[&c], vec![a]);
+        assert_eq!(parents[&a], vec![]);
+    }
+
+    #[test]
+    fn follows_the_same_parent_of_merges() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[("file", "1")], &[], 1);
+        let b = commit(&repo, &[("file", "2")], &[a], 2);
+        let c = commit(&repo, &[("file", "1"), ("other", "1")], &[a], 3);
+        // Takes `file` from `b`, so it didn't change it
+        let merge = commit(&repo, &[("file", "2"), ("other", "1")], &[c, b], 4);
+
+        let (ids, parents) =
+            simplify_by_path(&repo, vec![merge, c, b, a], Path::new("file"), false).unwrap();
+
+        assert_eq!(ids, vec![b, a]);
+        assert_eq!(parents[&b], vec![a]);
+    }
+
+    fn layout(commits: impl Iterator<Item = PositionedCommit>) -> String {
+        serde_json::to_string(&commits.collect_vec()).unwrap()
+    }
+
+    // The graph `update_positioned_commits` leaves after moving from `previous_tips`.
+    fn updated_layout(
+        repo: &Repository,
+        previous: Vec<PositionedCommit>,
+        previous_tips: &[String],
+    ) -> String {
+        let update =
+            update_positioned_commits(repo, &HistoryOptions::default(), &previous, previous_tips)
+                .unwrap()
+                .unwrap();
+        layout(
+            update
+                .rows
+                .into_iter()
+                .chain(previous.into_iter().skip(update.replaced)),
+        )
+    }
+
+    #[test]
+    fn updates_with_new_commits_on_top() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 1);
+        let b = commit(&repo, &[], &[a], 2);
+        repo.reference("refs/heads/main", b, true, "").unwrap();
+        let options = HistoryOptions::default();
+        let previous = get_positioned_commits(&repo, &options)
+            .unwrap()
+            .collect_vec();
+        let previous_tips = get_ref_tips(&repo).unwrap();
+
+        let c = commit(&repo, &[], &[b], 3);
+        repo.reference("refs/heads/main", c, true, "").unwrap();
+
+        assert_eq!(
+            updated_layout(&repo, previous, &previous_tips),
+            layout(get_positioned_commits(&repo, &options).unwrap())
+        );
+    }
+
+    #[test]
+    fn updates_with_a_fetched_branch_with_older_dates() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 1);
+        let b = commit(&repo, &[], &[a], 3);
+        let c = commit(&repo, &[], &[b], 5);
+        let d = commit(&repo, &[], &[c], 6);
+        repo.reference("refs/heads/main", d, true, "").unwrap();
+        let options = HistoryOptions::default();
+        let previous = get_positioned_commits(&repo, &options)
+            .unwrap()
+            .collect_vec();
+        let previous_tips = get_ref_tips(&repo).unwrap();
+
+        let f1 = commit(&repo, &[], &[a], 2);
+        let f2 = commit(&repo, &[], &[f1], 4);
+        repo.reference("refs/remotes/origin/feature", f2, true, "")
+            .unwrap();
+
+        let full = get_positioned_commits(&repo, &options)
+            .unwrap()
+            .collect_vec();
+        assert_eq!(
+            full.iter().map(|row| row.commit.id.clone()).collect_vec(),
+            [d, c, f2, b, f1, a].map(|oid| oid.to_string())
+        );
+        assert_eq!(
+            updated_layout(&repo, previous, &previous_tips),
+            layout(full.into_iter())
+        );
+    }
+
+    #[test]
+    fn hides_stash_internals() {
+        let (dir, mut repo) = init();
+        std::fs::write(dir.path().join("file"), "1").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::new("Test", "test@example.com", &Time::new(1, 0)).unwrap();
+        let base = repo
+            .commit(Some("HEAD"), &signature, &signature, "base", &tree, &[])
+            .unwrap();
+        drop(tree);
+
+        // Staged and untracked changes, each goes into its own internal commit
+        std::fs::write(dir.path().join("file"), "2").unwrap();
+        index.add_path(Path::new("file")).unwrap();
+        index.write().unwrap();
+        std::fs::write(dir.path().join("untracked"), "1").unwrap();
+        let stash = repo
+            .stash_save(
+                &signature,
+                "stash",
+                Some(git2::StashFlags::INCLUDE_UNTRACKED),
+            )
+            .unwrap();
+        assert_eq!(repo.find_commit(stash).unwrap().parent_count(), 3);
+
+        let rows = get_positioned_commits(&repo, &HistoryOptions::default())
+            .unwrap()
+            .collect_vec();
+
+        assert_eq!(
+            rows.iter().map(|row| row.commit.id.clone()).collect_vec(),
+            [stash, base].map(|oid| oid.to_string())
+        );
+        assert!(matches!(rows[0].node, NodeType::Stash(0)));
+        assert_eq!(rows[0].commit.parents, vec![base.to_string()]);
+        let parent_paths = rows[0]
+            .paths
+            .iter()
+            .filter(|path| matches!(path, BranchPath::Parent(_)))
+            .count();
+        assert_eq!(parent_paths, 1);
+    }
+
+    #[test]
+    fn keeps_lane_ids_across_rows() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 1);
+        let b = commit(&repo, &[], &[a], 3);
+        let c = commit(&repo, &[], &[a], 2);
+        let merge = commit(&repo, &[], &[b, c], 4);
+        let d = commit(&repo, &[], &[a], 5);
+        repo.reference("refs/heads/main", merge, true, "").unwrap();
+        repo.reference("refs/heads/other", d, true, "").unwrap();
+
+        let rows = get_positioned_commits(&repo, &HistoryOptions::default())
+            .unwrap()
+            .collect_vec();
+        let lane_of = |oid: Oid| {
+            rows.iter()
+                .find(|row| row.commit.id == oid.to_string())
+                .unwrap()
+                .lane
+        };
+
+        // Lines keep the id of the first commit they were opened for
+        assert_eq!(lane_of(d), lane_id(d));
+        assert_eq!(lane_of(merge), lane_id(merge));
+        assert_eq!(lane_of(b), lane_id(merge));
+        assert_eq!(lane_of(c), lane_id(c));
+
+        // What leaves a row is what the next one gets
+        let lanes = |row: &PositionedCommit, top: bool| {
+            row.paths
+                .iter()
+                .filter_map(|path| match path {
+                    BranchPath::Base(lane) if top => Some((lane.position, lane.id)),
+                    BranchPath::Parent(lane) if !top => Some((lane.position, lane.id)),
+                    BranchPath::Follow(lane) => Some((lane.position, lane.id)),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>()
+        };
+        for (row, next) in rows.iter().tuple_windows() {
+            assert_eq!(lanes(row, false), lanes(next, true));
+        }
+
+        let again = get_positioned_commits(&repo, &HistoryOptions::default()).unwrap();
+        assert_eq!(layout(rows.into_iter()), layout(again));
+    }
+
+    #[test]
+    fn keeps_head_on_the_first_column_when_compact() {
+        let (_dir, repo) = init();
+        let a = commit(&repo, &[], &[], 10);
+        let b = commit(&repo, &[], &[a], 20);
+        let c = commit(&repo, &[], &[b], 30);
+        let head = commit(&repo, &[], &[c], 40);
+        // Side branches newer than HEAD come up first
+        let f = commit(&repo, &[], &[a], 80);
+        let g = commit(&repo, &[], &[c], 70);
+        let k = commit(&repo, &[], &[a], 25);
+        repo.reference("refs/heads/main", head, true, "").unwrap();
+        repo.reference("refs/heads/f", f, true, "").unwrap();
+        repo.reference("refs/heads/g", g, true, "").unwrap();
+        repo.reference("refs/heads/k", k, true, "").unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+
+        let options = HistoryOptions {
+            layout: HistoryLayout::Compact,
+            ..Default::default()
+        };
+        let positions = get_positioned_commits(&repo, &options)
+            .unwrap()
+            .map(|row| (row.commit.id, row.position))
+            .collect_vec();
+
+        // `k` takes the column `g` left once it joined HEAD's chain
+        assert_eq!(
+            positions,
+            [(f, 1), (g, 2), (head, 0), (c, 0), (k, 2), (b, 0), (a, 0)]
+                .map(|(oid, position)| (oid.to_string(), position))
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use log::error;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::fs;
+use std::{collections::HashMap, fs};
 use tauri::api::path;
 
+use crate::positioned_commit::HistoryLayout;
+
 /// Opened Repo ///
 pub struct OpenRepo {}
 impl StringSettings for OpenRepo {
@@ -43,6 +45,18 @@ impl JsonSettings for DiffSettings {
     }
 }
 
+/// Graph Settings ///
+#[derive(Default, Serialize, Deserialize)]
+pub struct GraphSettings {
+    // By repo path
+    pub layouts: HashMap<String, HistoryLayout>,
+}
+impl JsonSettings for GraphSettings {
+    fn get_filename() -> &'static str {
+        "graphsettings"
+    }
+}
+
 /// Generics ///
 trait JsonSettings {
     fn get_filename() -> &'static str;
@@ -0,0 +1,652 @@
+use git2::{Commit, Config, Repository, Tag};
+use log::error;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::{
+    env,
+    fs::{self, DirBuilder, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SignatureKind {
+    Gpg,
+    Ssh,
+    X509,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignerInfo {
+    pub kind: SignatureKind,
+    // Key id for gpg, fingerprint for ssh
+    pub key_id: Option<String>,
+    pub signer: Option<String>,
+}
+
+impl SignerInfo {
+    fn new(kind: &SignatureKind) -> Self {
+        SignerInfo {
+            kind: kind.clone(),
+            key_id: None,
+            signer: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum SignatureStatus {
+    Unsigned,
+    // Signed, but it hasn't been checked, or it can't be (unknown key, no allowed signers...),
+    // or it's right but made with a key that isn't trusted.
+    Unverified(SignerInfo),
+    Good(SignerInfo),
+    Bad(SignerInfo),
+}
+
+/// Checks a detached signature against the data that was signed.
+pub trait SignatureVerifier {
+    fn verify(&self, signature: &[u8], data: &[u8]) -> Result<SignatureStatus, String>;
+}
+
+// Commit header the signature goes in, `gpgsig-sha256` in SHA-256 repos
+const SIGNATURE_FIELDS: [&str; 2] = ["gpgsig", "gpgsig-sha256"];
+
+/// Whether the commit is signed, without checking the signature, which is cheap enough
+/// to do for every commit in the graph.
+pub fn get_signature_status(commit: &Commit) -> SignatureStatus {
+    let signature = SIGNATURE_FIELDS
+        .iter()
+        .find_map(|field| commit.header_field_bytes(field).ok());
+    match signature {
+        Some(signature) => {
+            SignatureStatus::Unverified(SignerInfo::new(&get_signature_kind(&signature)))
+        }
+        None => SignatureStatus::Unsigned,
+    }
+}
+
+/// Checks the signature of the commit with the verifier the repo is configured with.
+pub fn verify_commit(repo: &Repository, commit: &Commit) -> SignatureStatus {
+    let signature = SIGNATURE_FIELDS
+        .iter()
+        .find_map(|field| repo.extract_signature(&commit.id(), Some(field)).ok());
+    match signature {
+        Some((signature, data)) => verify_signature(repo, &signature, &data),
+        None => SignatureStatus::Unsigned,
+    }
+}
+
+/// Same as `get_signature_status`, for annotated tags.
+pub fn get_tag_signature_status(tag: &Tag) -> SignatureStatus {
+    let message = tag.message_bytes().unwrap_or_default();
+    match find_tag_signature(message) {
+        Some(start) => {
+            SignatureStatus::Unverified(SignerInfo::new(&get_signature_kind(&message[start..])))
+        }
+        None => SignatureStatus::Unsigned,
+    }
+}
+
+/// Message of an annotated tag, without its signature.
+pub fn get_tag_message(tag: &Tag) -> Option<String> {
+    let message = tag.message_bytes()?;
+    let end = find_tag_signature(message).unwrap_or(message.len());
+    Some(String::from_utf8_lossy(&message[..end]).into_owned())
+}
+
+/// Tags don't have a signature header like commits, it's appended to the message instead,
+/// and everything that comes before it is what was signed.
+pub fn verify_tag(repo: &Repository, tag: &Tag) -> SignatureStatus {
+    let content = match repo
+        .odb()
+        .and_then(|odb| odb.read(tag.id()).map(|object| object.data().to_vec()))
+    {
+        Ok(content) => content,
+        Err(_) => return SignatureStatus::Unsigned,
+    };
+    match find_tag_signature(&content) {
+        Some(start) => verify_signature(repo, &content[start..], &content[..start]),
+        None => SignatureStatus::Unsigned,
+    }
+}
+
+// The signature is the last block, as in git. The message itself could quote one.
+fn find_tag_signature(content: &[u8]) -> Option<usize> {
+    [
+        b"\n-----BEGIN PGP SIGNATURE-----".as_slice(),
+        b"\n-----BEGIN SSH SIGNATURE-----",
+        b"\n-----BEGIN SIGNED MESSAGE-----",
+    ]
+    .iter()
+    .filter_map(|header| {
+        content
+            .windows(header.len())
+            .rposition(|window| window.eq(*header))
+    })
+    .max()
+    // Past the line break
+    .map(|position| position + 1)
+}
+
+pub fn verify_signature(repo: &Repository, signature: &[u8], data: &[u8]) -> SignatureStatus {
+    let kind = get_signature_kind(signature);
+    let verifier = repo
+        .config()
+        .ok()
+        .map(|config| get_verifier(&config, &kind));
+
+    match verifier.map(|verifier| verifier.verify(signature, data)) {
+        Some(Ok(status)) => status,
+        Some(Err(e)) => {
+            error!("Error verifying {:?} signature {}", kind, e);
+            SignatureStatus::Unverified(SignerInfo::new(&kind))
+        }
+        None => SignatureStatus::Unverified(SignerInfo::new(&kind)),
+    }
+}
+
+fn get_signature_kind(signature: &[u8]) -> SignatureKind {
+    if signature.starts_with(b"-----BEGIN PGP SIGNATURE-----") {
+        SignatureKind::Gpg
+    } else if signature.starts_with(b"-----BEGIN SSH SIGNATURE-----") {
+        SignatureKind::Ssh
+    } else {
+        SignatureKind::X509
+    }
+}
+
+// Same settings git uses for `git verify-commit`
+fn get_verifier(config: &Config, kind: &SignatureKind) -> Box<dyn SignatureVerifier> {
+    match kind {
+        SignatureKind::Gpg => Box::new(GpgVerifier {
+            kind: kind.clone(),
+            program: config
+                .get_string("gpg.openpgp.program")
+                .or_else(|_| config.get_string("gpg.program"))
+                .unwrap_or("gpg".to_owned()),
+        }),
+        SignatureKind::X509 => Box::new(GpgVerifier {
+            kind: kind.clone(),
+            program: config
+                .get_string("gpg.x509.program")
+                .unwrap_or("gpgsm".to_owned()),
+        }),
+        SignatureKind::Ssh => Box::new(SshVerifier {
+            program: config
+                .get_string("gpg.ssh.program")
+                .unwrap_or("ssh-keygen".to_owned()),
+            allowed_signers: config.get_path("gpg.ssh.allowedSignersFile").ok(),
+        }),
+    }
+}
+
+/// Verifies through `gpg` (or `gpgsm`), with the trust the user's keyring has in each key.
+pub struct GpgVerifier {
+    kind: SignatureKind,
+    program: String,
+}
+
+impl SignatureVerifier for GpgVerifier {
+    fn verify(&self, signature: &[u8], data: &[u8]) -> Result<SignatureStatus, String> {
+        let signature_file = TempFile::new(signature)?;
+        let output = run(
+            Command::new(&self.program)
+                .arg("--status-fd=1")
+                .arg("--verify")
+                .arg(signature_file.path())
+                .arg("-"),
+            data,
+        )?;
+
+        Ok(parse_gpg_status(
+            &self.kind,
+            &String::from_utf8_lossy(&output.stdout),
+        ))
+    }
+}
+
+// https://github.com/gpg/gnupg/blob/master/doc/DETAILS#format-of-the-status-fd-output
+fn parse_gpg_status(kind: &SignatureKind, status: &str) -> SignatureStatus {
+    let mut good = None;
+    let mut trusted = false;
+    for line in status.lines() {
+        let mut parts = match line.strip_prefix("[GNUPG:] ") {
+            Some(line) => line.splitn(3, ' '),
+            None => continue,
+        };
+        let keyword = parts.next();
+        let info = SignerInfo {
+            kind: kind.clone(),
+            key_id: parts.next().map(|v| v.to_owned()),
+            signer: parts.next().map(|v| v.to_owned()),
+        };
+        match keyword {
+            Some("GOODSIG") => good = Some(info),
+            Some("BADSIG") | Some("REVKEYSIG") => return SignatureStatus::Bad(info),
+            // Expired signature or key: it's right, but it can't be trusted anymore
+            Some("EXPSIG") | Some("EXPKEYSIG") => {
+                return SignatureStatus::Unverified(info);
+            }
+            // The key is missing, only its id is known
+            Some("ERRSIG") => {
+                return SignatureStatus::Unverified(SignerInfo {
+                    signer: None,
+                    ..info
+                })
+            }
+            // Like git, a good signature only counts from marginal trust up.
+            // TRUST_UNDEFINED and TRUST_NEVER leave it unverified.
+            Some("TRUST_MARGINAL") | Some("TRUST_FULLY") | Some("TRUST_ULTIMATE") => trusted = true,
+            _ => {}
+        }
+    }
+    match (good, trusted) {
+        (Some(info), true) => SignatureStatus::Good(info),
+        (Some(info), false) => SignatureStatus::Unverified(info),
+        (None, _) => SignatureStatus::Unverified(SignerInfo::new(kind)),
+    }
+}
+
+/// Verifies through `ssh-keygen -Y`. Without an allowed signers file, there's no way
+/// to know who the key belongs to, so the signature can only be checked for integrity.
+pub struct SshVerifier {
+    program: String,
+    allowed_signers: Option<PathBuf>,
+}
+
+impl SignatureVerifier for SshVerifier {
+    fn verify(&self, signature: &[u8], data: &[u8]) -> Result<SignatureStatus, String> {
+        let signature_file = TempFile::new(signature)?;
+
+        let principal = match &self.allowed_signers {
+            Some(allowed_signers) => {
+                let output = run(
+                    Command::new(&self.program)
+                        .args(["-Y", "find-principals", "-f"])
+                        .arg(allowed_signers)
+                        .arg("-s")
+                        .arg(signature_file.path()),
+                    &[],
+                )?;
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .next()
+                    .filter(|_| output.status.success())
+                    .map(|line| line.to_owned())
+            }
+            None => None,
+        };
+
+        let output = match (&self.allowed_signers, &principal) {
+            (Some(allowed_signers), Some(principal)) => run(
+                Command::new(&self.program)
+                    .args(["-Y", "verify", "-n", "git", "-f"])
+                    .arg(allowed_signers)
+                    .arg("-I")
+                    .arg(principal)
+                    .arg("-s")
+                    .arg(signature_file.path()),
+                data,
+            )?,
+            _ => run(
+                Command::new(&self.program)
+                    .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+                    .arg(signature_file.path()),
+                data,
+            )?,
+        };
+
+        // Good "git" signature for <principal> with ED25519 key SHA256:...
+        let key_id = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .chain(String::from_utf8_lossy(&output.stderr).lines())
+            .find_map(|line| line.split(" key ").nth(1).map(|key| key.trim().to_owned()));
+        let info = SignerInfo {
+            kind: SignatureKind::Ssh,
+            key_id,
+            signer: principal.clone(),
+        };
+
+        Ok(match (output.status.success(), principal) {
+            (false, _) => SignatureStatus::Bad(info),
+            (true, Some(_)) => SignatureStatus::Good(info),
+            (true, None) => SignatureStatus::Unverified(info),
+        })
+    }
+}
+
+/// Signs `data` with the key and format the repo is configured with (`gpg.format` and
+/// `user.signingKey`, as `git tag -s` does). Returns the armored signature.
+pub fn sign(repo: &Repository, data: &[u8]) -> Result<String, String> {
+    let config = repo.config().map_err(|e| e.message().to_owned())?;
+    let format = config
+        .get_string("gpg.format")
+        .unwrap_or("openpgp".to_owned());
+    // gpg falls back to the committer identity, ssh needs a key to sign with.
+    let key = config.get_string("user.signingKey").or_else(|_| {
+        repo.signature()
+            .map(|signature| signature.to_string())
+            .map_err(|e| e.message().to_owned())
+    });
+
+    let output = match format.as_str() {
+        "openpgp" | "x509" => {
+            let program = if format == "x509" {
+                config
+                    .get_string("gpg.x509.program")
+                    .unwrap_or("gpgsm".to_owned())
+            } else {
+                config
+                    .get_string("gpg.openpgp.program")
+                    .or_else(|_| config.get_string("gpg.program"))
+                    .unwrap_or("gpg".to_owned())
+            };
+            let output = run(
+                Command::new(program)
+                    .arg("--status-fd=2")
+                    .arg("-bsau")
+                    .arg(key?),
+                data,
+            )?;
+            if !output.status.success() {
+                return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+            }
+            output.stdout
+        }
+        "ssh" => {
+            let program = config
+                .get_string("gpg.ssh.program")
+                .unwrap_or("ssh-keygen".to_owned());
+            let key = config
+                .get_string("user.signingKey")
+                .map_err(|_| "user.signingKey is needed to sign with ssh".to_owned())?;
+            let data_file = TempFile::new(data)?;
+            let mut command = Command::new(program);
+            command.args(["-Y", "sign", "-n", "git", "-f"]);
+
+            // Either a path to the key, or the public key itself when the private one is in the agent
+            let key_file = match key.strip_prefix("key::") {
+                Some(key) => Some(TempFile::new(key.as_bytes())?),
+                None if key.starts_with("ssh-") => Some(TempFile::new(key.as_bytes())?),
+                None => None,
+            };
+            // `-U` tells ssh-keygen that the file has the public key, to be found in the agent.
+            match &key_file {
+                Some(key_file) => command.arg(key_file.path()).arg("-U"),
+                None => command.arg(shellexpand_home(&key)),
+            };
+
+            let output = run(command.arg(data_file.path()), &[])?;
+            if !output.status.success() {
+                return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+            }
+            // Written next to the data, so it goes away along with it
+            let signature_file = format!("{}.sig", data_file.path().display());
+            fs::read(signature_file).map_err(|e| e.to_string())?
+        }
+        format => return Err(format!("Unsupported gpg.format {}", format)),
+    };
+
+    let signature = String::from_utf8_lossy(&output).into_owned();
+    if signature.is_empty() {
+        return Err(format!("Signing with {} failed", format));
+    }
+    Ok(signature)
+}
+
+fn shellexpand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(path), Ok(home)) => PathBuf::from(home).join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn run(command: &mut Command, input: &[u8]) -> Result<Output, String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Can't run {:?}: {}", command.get_program(), e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input).map_err(|e| e.to_string())?;
+    }
+    child.wait_with_output().map_err(|e| e.to_string())
+}
+
+// The signature has to be passed as a file, while the signed data goes through stdin.
+// Also used for the files `ssh-keygen -Y sign` reads and writes. Each one goes in a
+// directory of its own that only the user can access, removed along with the file.
+struct TempFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+static TEMP_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl TempFile {
+    fn new(content: &[u8]) -> Result<Self, String> {
+        let dir = create_private_dir().map_err(|e| e.to_string())?;
+        let file = TempFile {
+            path: dir.join("file"),
+            dir,
+        };
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options
+            .open(&file.path)
+            .and_then(|mut handle| handle.write_all(content))
+            .map_err(|e| e.to_string())?;
+        Ok(file)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+// Creating it fails if anything is already there, so it can't be a link placed in advance.
+fn create_private_dir() -> io::Result<PathBuf> {
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    builder.mode(0o700);
+
+    let mut attempts = 0;
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or_default();
+        let dir = env::temp_dir().join(format!(
+            "git_gui-{}-{}-{}",
+            process::id(),
+            TEMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed),
+            nanos
+        ));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PGP_SIGNATURE: &str =
+        "-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----\n";
+
+    #[test]
+    fn finds_no_signature_in_unsigned_tags() {
+        let content = "object abc\ntype commit\ntag v1\n\nRelease\n";
+
+        assert_eq!(find_tag_signature(content.as_bytes()), None);
+    }
+
+    #[test]
+    fn finds_tag_signature() {
+        let message = "object abc\ntype commit\ntag v1\n\nRelease\n";
+        let content = format!("{}{}", message, PGP_SIGNATURE);
+
+        assert_eq!(find_tag_signature(content.as_bytes()), Some(message.len()));
+    }
+
+    #[test]
+    fn finds_ssh_tag_signature() {
+        let message = "object abc\ntype commit\ntag v1\n\nRelease\n";
+        let content = format!(
+            "{}-----BEGIN SSH SIGNATURE-----\nabc\n-----END SSH SIGNATURE-----\n",
+            message
+        );
+
+        assert_eq!(find_tag_signature(content.as_bytes()), Some(message.len()));
+    }
+
+    #[test]
+    fn takes_the_last_signature_block() {
+        // The message quotes a signature, the real one is appended after it
+        let message = format!(
+            "object abc\ntype commit\ntag v1\n\nQuoting:\n{}\nEnd of quote\n",
+            PGP_SIGNATURE
+        );
+        let content = format!("{}{}", message, PGP_SIGNATURE);
+
+        assert_eq!(find_tag_signature(content.as_bytes()), Some(message.len()));
+    }
+
+    fn gpg_status(trust: &str) -> SignatureStatus {
+        let status = format!(
+            "[GNUPG:] NEWSIG\n\
+             [GNUPG:] GOODSIG 1234ABCD Test <test@example.com>\n\
+             [GNUPG:] VALIDSIG ABCDEF 2024-01-01 1704067200 0 4 0 1 10 00 ABCDEF\n\
+             {trust}"
+        );
+        parse_gpg_status(&SignatureKind::Gpg, &status)
+    }
+
+    #[test]
+    fn trusts_good_signatures_from_trusted_keys() {
+        let status = gpg_status("[GNUPG:] TRUST_FULLY 0 pgp\n");
+
+        assert!(matches!(
+            status,
+            SignatureStatus::Good(SignerInfo { key_id: Some(key_id), signer: Some(signer), .. })
+                if key_id == "1234ABCD" && signer == "Test <test@example.com>"
+        ));
+        assert!(matches!(
+            gpg_status("[GNUPG:] TRUST_MARGINAL 0 pgp\n"),
+            SignatureStatus::Good(_)
+        ));
+    }
+
+    #[test]
+    fn leaves_good_signatures_from_untrusted_keys_unverified() {
+        for trust in [
+            "[GNUPG:] TRUST_UNDEFINED 0 pgp\n",
+            "[GNUPG:] TRUST_NEVER 0 pgp\n",
+            "",
+        ] {
+            assert!(matches!(
+                gpg_status(trust),
+                SignatureStatus::Unverified(SignerInfo {
+                    key_id: Some(_),
+                    ..
+                })
+            ));
+        }
+    }
+
+    // An agent of its own, stopped along with the test
+    struct Agent {
+        process: process::Child,
+    }
+
+    impl Drop for Agent {
+        fn drop(&mut self) {
+            self.process.kill().ok();
+            self.process.wait().ok();
+        }
+    }
+
+    #[test]
+    fn signs_with_a_literal_agent_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let key = dir.path().join("key");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let public_key = fs::read_to_string(key.with_extension("pub")).unwrap();
+
+        let socket = dir.path().join("agent.sock");
+        let _agent = Agent {
+            process: Command::new("ssh-agent")
+                .arg("-D")
+                .arg("-a")
+                .arg(&socket)
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap(),
+        };
+        while !socket.exists() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let status = Command::new("ssh-add")
+            .arg(&key)
+            .env("SSH_AUTH_SOCK", &socket)
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        // Only the private key in the agent can sign from now on
+        fs::remove_file(&key).unwrap();
+        env::set_var("SSH_AUTH_SOCK", &socket);
+
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("gpg.format", "ssh").unwrap();
+        let allowed_signers = dir.path().join("allowed_signers");
+        fs::write(&allowed_signers, format!("test@example.com {public_key}")).unwrap();
+        let verifier = SshVerifier {
+            program: "ssh-keygen".to_owned(),
+            allowed_signers: Some(allowed_signers),
+        };
+
+        for signing_key in [
+            public_key.trim().to_owned(),
+            format!("key::{}", public_key.trim()),
+        ] {
+            config.set_str("user.signingKey", &signing_key).unwrap();
+
+            let signature = sign(&repo, b"data").unwrap();
+
+            assert!(signature.starts_with("-----BEGIN SSH SIGNATURE-----"));
+            let status = verifier.verify(signature.as_bytes(), b"data").unwrap();
+            assert!(matches!(
+                status,
+                SignatureStatus::Good(SignerInfo { signer: Some(signer), .. })
+                    if signer == "test@example.com"
+            ));
+        }
+    }
+}
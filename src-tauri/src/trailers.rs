@@ -0,0 +1,159 @@
+use git2::{message_trailers_strs, Commit, Mailmap, Signature};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::positioned_commit::SignatureInfo;
+
+/// Trailers at the end of the commit message (`Key: value` lines, see `git interpret-trailers`).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CommitTrailers {
+    pub co_authors: Vec<SignatureInfo>,
+    pub signed_off_by: Vec<String>,
+    pub reviewed_by: Vec<String>,
+    pub fixes: Vec<String>,
+    // Any other trailer, in the order they show up
+    pub other: Vec<(String, String)>,
+}
+
+impl CommitTrailers {
+    /// Co-authors go through `mailmap` too, and take the time of the author.
+    pub fn new(commit: &Commit, mailmap: &Mailmap) -> Self {
+        let mut result = CommitTrailers::default();
+        let trailers = match commit.message().map(message_trailers_strs) {
+            Some(Ok(trailers)) => trailers,
+            _ => return result,
+        };
+
+        for (key, value) in trailers.iter() {
+            // Continuation lines are unfolded, as `git interpret-trailers --parse` does
+            let value = value.lines().map(|line| line.trim()).join(" ");
+            match key.to_lowercase().as_str() {
+                "co-authored-by" => result
+                    .co_authors
+                    .push(get_co_author(commit, mailmap, &value)),
+                "signed-off-by" => result.signed_off_by.push(value),
+                "reviewed-by" => result.reviewed_by.push(value),
+                "fixes" => result.fixes.push(value),
+                _ => result.other.push((key.to_owned(), value)),
+            }
+        }
+        result
+    }
+}
+
+// `Name <email>`
+fn get_co_author(commit: &Commit, mailmap: &Mailmap, value: &str) -> SignatureInfo {
+    let (name, email) = match value.rsplit_once('<') {
+        Some((name, email)) => (name.trim(), Some(email.trim_end_matches('>').trim())),
+        None => (value, None),
+    };
+
+    email
+        .and_then(|email| Signature::new(name, email, &commit.author().when()).ok())
+        .and_then(|signature| mailmap.resolve_signature(&signature).ok())
+        .map(|signature| SignatureInfo::new(&signature))
+        .unwrap_or_else(|| {
+            let mut info = SignatureInfo::new(&commit.author());
+            info.name = Some(name.to_owned());
+            info.email = email.map(|email| email.to_owned());
+            info.hash = None;
+            info
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Repository, Time};
+    use tempfile::TempDir;
+
+    fn trailers(message: &str, mailmap: &Mailmap) -> CommitTrailers {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let signature = Signature::new("Test", "test@example.com", &Time::new(100, 0)).unwrap();
+        let id = repo
+            .commit(None, &signature, &signature, message, &tree, &[])
+            .unwrap();
+        let commit = repo.find_commit(id).unwrap();
+        CommitTrailers::new(&commit, mailmap)
+    }
+
+    #[test]
+    fn reads_known_trailers() {
+        let message = "Fix the parser\n\
+                       \n\
+                       Some details.\n\
+                       \n\
+                       Co-authored-by: Jane Doe <jane@example.com>\n\
+                       Signed-off-by: Test <test@example.com>\n\
+                       Reviewed-by: Someone <someone@example.com>\n\
+                       Fixes: #123\n\
+                       Change-Id: I1234\n";
+
+        let result = trailers(message, &Mailmap::new().unwrap());
+
+        assert_eq!(result.co_authors.len(), 1);
+        assert_eq!(result.co_authors[0].name.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            result.co_authors[0].email.as_deref(),
+            Some("jane@example.com")
+        );
+        // Same time as the author
+        assert_eq!(result.co_authors[0].time, 100);
+        assert_eq!(result.signed_off_by, vec!["Test <test@example.com>"]);
+        assert_eq!(result.reviewed_by, vec!["Someone <someone@example.com>"]);
+        assert_eq!(result.fixes, vec!["#123"]);
+        assert_eq!(
+            result.other,
+            vec![("Change-Id".to_owned(), "I1234".to_owned())]
+        );
+    }
+
+    #[test]
+    fn resolves_co_authors_through_the_mailmap() {
+        let mut mailmap = Mailmap::new().unwrap();
+        mailmap
+            .add_entry(
+                Some("Jane Doe"),
+                Some("jane@example.com"),
+                None,
+                "jane@old.example.com",
+            )
+            .unwrap();
+        let message = "Fix\n\nCo-authored-by: jane <jane@old.example.com>\n";
+
+        let result = trailers(message, &mailmap);
+
+        assert_eq!(result.co_authors[0].name.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            result.co_authors[0].email.as_deref(),
+            Some("jane@example.com")
+        );
+    }
+
+    #[test]
+    fn joins_continuation_lines() {
+        let message =
+            "Fix\n\nFixes: a very long\n  description\nSigned-off-by: Test <test@example.com>\n";
+
+        let result = trailers(message, &Mailmap::new().unwrap());
+
+        assert_eq!(result.fixes, vec!["a very long description"]);
+        assert_eq!(result.signed_off_by, vec!["Test <test@example.com>"]);
+    }
+
+    #[test]
+    fn reads_no_trailers_from_plain_messages() {
+        let message = "Fix the parser\n\nIt broke with: colons in the body\n\nAnd more text.\n";
+
+        let result = trailers(message, &Mailmap::new().unwrap());
+
+        assert!(result.co_authors.is_empty());
+        assert!(result.signed_off_by.is_empty());
+        assert!(result.fixes.is_empty());
+        assert!(result.other.is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 import { invoke } from "@tauri-apps/api";
 import { filter, from, startWith, switchMap, withLatestFrom } from "rxjs";
 import { activeCommit$ } from "../RepoGrid/activeCommit";
-import { repoPath$ } from "../repoState";
+import { repoPath$, type SignatureStatus } from "../repoState";
 
 export interface File {
   id: string;
@@ -26,6 +26,8 @@ export interface CommitContents {
   insertions: number;
   deletions: number;
   deltas: Array<Delta>;
+  signature: SignatureStatus;
+  note: string | null;
 }
 
 export const commitChanges$ = activeCommit$.pipeState(
@@ -1,5 +1,5 @@
 import { appBgColor } from "@/style.css";
-import type { BranchPath, PositionedCommit } from "../repoState";
+import type { BranchPath, Lane, PositionedCommit } from "../repoState";
 import {
   activeCommitBgColor,
   hoverBgColor,
@@ -17,7 +17,7 @@ export const getMaxWidth = (commits: PositionedCommit[]) => {
     commits
       ?.flatMap((positioned) => [
         positioned.position,
-        ...positioned.paths.map((path) => path.payload),
+        ...positioned.paths.map((path) => path.payload.position),
       ])
       .reduce((a, b) => Math.max(a, b), 0) ?? 0;
   return getPositionMaxX(position + 1); // Add one to account for gradient
@@ -32,7 +32,8 @@ const BASE_COLOR = 150;
 // Derived from phi (227.5º -> 137.5º), maths in https://r-knott.surrey.ac.uk/Fibonacci/fibnat2.html
 // I prefer a rainbow effect. 53 because it's a smallish prime number, far from any factor of 360 (between 45 and 60)
 // Alternatives would be 79 [72,90], 31 or 33 [30, 36], and 27 or 29 [24,30]
-const getColor = (i: number) => `hsl(${BASE_COLOR + i * 53}, 100%, 75%)`;
+const getColor = (i: number) =>
+  `hsl(${(BASE_COLOR + i * 53) % 360}, 100%, 75%)`;
 
 const gravatarImages = new Map<
   string,
@@ -168,13 +169,14 @@ function drawBase(
   ctx: CanvasRenderingContext2D,
   width: number,
   commitPos: number,
-  pos: number
+  lane: Lane
 ) {
+  const pos = lane.position;
   if (getPositionMaxX(Math.min(commitPos, pos)) > width) {
     return;
   }
   ctx.beginPath();
-  ctx.strokeStyle = getColor(pos);
+  ctx.strokeStyle = getColor(lane.id);
   ctx.lineWidth = 2;
   ctx.lineCap = "round";
   ctx.moveTo(getPositionX(commitPos), ITEM_HEIGHT / 2);
@@ -186,13 +188,18 @@ function drawBase(
   ctx.lineTo(getPositionX(pos), 0);
   ctx.stroke();
 }
-function drawFollow(ctx: CanvasRenderingContext2D, width: number, pos: number) {
+function drawFollow(
+  ctx: CanvasRenderingContext2D,
+  width: number,
+  lane: Lane
+) {
+  const pos = lane.position;
   if (getPositionMaxX(pos) > width) {
     return;
   }
 
   ctx.beginPath();
-  ctx.strokeStyle = getColor(pos);
+  ctx.strokeStyle = getColor(lane.id);
   ctx.lineWidth = 2;
   ctx.lineCap = "round";
   ctx.moveTo(getPositionX(pos), 0);
@@ -203,13 +210,14 @@ function drawParent(
   ctx: CanvasRenderingContext2D,
   width: number,
   commitPos: number,
-  pos: number
+  lane: Lane
 ) {
+  const pos = lane.position;
   if (getPositionMaxX(Math.min(commitPos, pos)) > width) {
     return;
   }
   ctx.beginPath();
-  ctx.strokeStyle = getColor(pos);
+  ctx.strokeStyle = getColor(lane.id);
   ctx.lineWidth = 2;
   ctx.lineCap = "round";
   ctx.moveTo(getPositionX(commitPos), ITEM_HEIGHT / 2);
@@ -1,6 +1,6 @@
 import { map } from "rxjs";
 import { refs$, RefType } from "../repoState";
-import type { LocalRef, RemoteRef } from "../repoState";
+import type { LocalRef, RemoteRef, TagRef } from "../repoState";
 
 // e.g. { "abcdef1234": {...} }
 export type RefsLookup = Record<string, RefGroups>;
@@ -15,8 +15,9 @@ export interface RefGroup {
 }
 
 export type LookedUpRef =
-  | { type: RefType.LocalBranch | RefType.Tag | RefType.Head; ref: LocalRef }
-  | { type: RefType.RemoteBranch; ref: RemoteRef };
+  | { type: RefType.LocalBranch | RefType.Head; ref: LocalRef }
+  | { type: RefType.RemoteBranch; ref: RemoteRef }
+  | { type: RefType.Tag; ref: TagRef };
 
 export const refsLookup$ = refs$.pipeState(
   map((refs): RefsLookup => {
@@ -0,0 +1,82 @@
+import { listen$ } from "@/lib/tauriRx";
+import { state } from "@react-rxjs/core";
+import { createSignal } from "@react-rxjs/utils";
+import { invoke } from "@tauri-apps/api";
+import { map, merge, scan } from "rxjs";
+
+export interface HostKey {
+  host: string;
+  key_type: string;
+  fingerprint: string;
+}
+
+export type HostKeyError =
+  | { type: "Changed"; payload: HostKey }
+  | { type: "Untrusted"; payload: HostKey }
+  | { type: "Revoked"; payload: HostKey }
+  | { type: "Unsupported"; payload: string }
+  | { type: "Lookup"; payload: string };
+
+export const describeHostKeyError = (error: HostKeyError) => {
+  switch (error.type) {
+    case "Changed":
+      return `Host key for ${error.payload.host} has changed!`;
+    case "Untrusted":
+      return `Host ${error.payload.host} isn't trusted`;
+    case "Revoked":
+      return `Host key for ${error.payload.host} has been revoked!`;
+    case "Unsupported":
+      return `Host key for ${error.payload} can't be checked`;
+    case "Lookup":
+      return `Can't check the host key: ${error.payload}`;
+  }
+};
+
+export type CredentialPrompt =
+  | { type: "Passphrase"; payload: string }
+  | { type: "UserPass"; payload: { username: string | null } }
+  | { type: "UnknownHost"; payload: HostKey };
+
+export interface CredentialRequest {
+  id: number;
+  url: string;
+  prompt: CredentialPrompt;
+}
+
+export type CredentialResponse =
+  | { type: "Passphrase"; payload: string }
+  | {
+      type: "UserPass";
+      payload: { username: string; password: string; store: boolean };
+    }
+  | { type: "TrustHost" };
+
+const [answered$, setAnswered] = createSignal<number>();
+
+// Prompts waiting for an answer, oldest first
+export const credentialRequests$ = state(
+  merge(
+    listen$<CredentialRequest>("credential-request").pipe(
+      map(({ payload }) => ({ type: "request" as const, payload }))
+    ),
+    answered$.pipe(map((id) => ({ type: "answered" as const, id })))
+  ).pipe(
+    scan(
+      (acc, evt) =>
+        evt.type === "request"
+          ? [...acc, evt.payload]
+          : acc.filter((request) => request.id !== evt.id),
+      [] as CredentialRequest[]
+    )
+  ),
+  []
+);
+
+/** `null` cancels the prompt */
+export function answerCredentials(
+  id: number,
+  response: CredentialResponse | null
+) {
+  setAnswered(id);
+  return invoke("answer_credentials", { id, response });
+}
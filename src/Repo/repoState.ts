@@ -3,7 +3,7 @@ import {
   losslessExhaustMap,
   losslessThrottle,
 } from "@/lib/rxState";
-import { listen$, streamCommand$ } from "@/lib/tauriRx";
+import { listen$, streamCommandResult$ } from "@/lib/tauriRx";
 import { state } from "@react-rxjs/core";
 import { createSignal } from "@react-rxjs/utils";
 import { invoke } from "@tauri-apps/api";
@@ -22,14 +22,17 @@ import {
   ignoreElements,
   map,
   merge,
+  mergeMap,
   scan,
   share,
   startWith,
   switchMap,
   take,
+  tap,
   timer,
   withLatestFrom,
 } from "rxjs";
+import { describeHostKeyError, type HostKeyError } from "./credentialsState";
 
 export const [triggerOpen$, openRepo] = createSignal();
 export const repoPath$ = state(
@@ -51,6 +54,27 @@ export interface SignatureInfo {
   email: string | null;
   hash: string | null;
   time: number; // epoch seconds
+  offset: number; // minutes east of UTC
+}
+
+export interface SignerInfo {
+  kind: "Gpg" | "Ssh" | "X509";
+  key_id: string | null;
+  signer: string | null;
+}
+
+export type SignatureStatus =
+  | { type: "Unsigned" }
+  | { type: "Unverified"; payload: SignerInfo }
+  | { type: "Good"; payload: SignerInfo }
+  | { type: "Bad"; payload: SignerInfo };
+
+export interface CommitTrailers {
+  co_authors: SignatureInfo[];
+  signed_off_by: string[];
+  reviewed_by: string[];
+  fixes: string[];
+  other: Array<[string, string]>;
 }
 
 export interface CommitInfo {
@@ -61,19 +85,31 @@ export interface CommitInfo {
   time: number; // epoch seconds
   author: SignatureInfo;
   committer: SignatureInfo;
+  signature: SignatureStatus;
+  trailers: CommitTrailers;
+}
+
+export interface Lane {
+  position: number;
+  id: number; // Stays the same along the whole branch
 }
 
 export interface BranchPath {
   type: "Base" | "Parent" | "Follow";
-  payload: number;
+  payload: Lane;
 }
 
+export type NodeType =
+  | { type: "Commit" }
+  | { type: "Stash"; payload: number };
+
 export interface PositionedCommit {
   id: string;
   commit: CommitInfo;
+  node: NodeType;
   descendants: Array<string>;
   position: number;
-  color: number;
+  lane: number;
   paths: Array<BranchPath>;
 }
 
@@ -82,6 +118,44 @@ const hasFocus$ = timer(0, 1000).pipe(
   distinctUntilChanged()
 );
 
+export type FetchStage =
+  | {
+      type: "Transfer";
+      payload: {
+        received_objects: number;
+        indexed_objects: number;
+        total_objects: number;
+        received_bytes: number;
+      };
+    }
+  | { type: "Sideband"; payload: string }
+  | {
+      type: "UpdatedRef";
+      payload: { name: string; old: string | null; new: string | null };
+    }
+  | { type: "Done"; payload: FetchError | null };
+
+export type FetchError =
+  | { type: "Read"; payload: string }
+  | { type: "Cancelled" }
+  | { type: "HostKey"; payload: HostKeyError };
+
+const describeFetchError = (error: FetchError) => {
+  switch (error.type) {
+    case "Read":
+      return error.payload;
+    case "Cancelled":
+      return "Cancelled";
+    case "HostKey":
+      return describeHostKeyError(error.payload);
+  }
+};
+
+export interface FetchProgress {
+  remote: string;
+  progress: FetchStage;
+}
+
 export const [startFetch$, fetch] = createSignal();
 export const isFetching$ = state(
   // merge(startFetch$, hasFocus$.pipe(filter((hasFocus) => hasFocus))).pipe(
@@ -104,6 +178,57 @@ export const isFetching$ = state(
   false
 );
 
+export const cancelFetch = () =>
+  invoke("cancel_fetch", { path: repoPath$.getValue() });
+
+// Latest transfer stats and message of every remote, for the fetch in progress
+export const fetchProgress$ = state(
+  startFetch$.pipe(
+    switchMap(() =>
+      listen$<FetchProgress>("fetch-progress").pipe(
+        map(({ payload }) => payload),
+        scan(
+          (acc, { remote, progress }) => {
+            const current = acc[remote] ?? { transfer: null, message: null };
+            switch (progress.type) {
+              case "Transfer":
+                return {
+                  ...acc,
+                  [remote]: { ...current, transfer: progress.payload },
+                };
+              case "Sideband":
+                return {
+                  ...acc,
+                  [remote]: { ...current, message: progress.payload },
+                };
+              case "Done":
+                return {
+                  ...acc,
+                  [remote]: {
+                    ...current,
+                    message: progress.payload
+                      ? describeFetchError(progress.payload)
+                      : null,
+                  },
+                };
+            }
+            return acc;
+          },
+          {} as Record<
+            string,
+            {
+              transfer: (FetchStage & { type: "Transfer" })["payload"] | null;
+              message: string | null;
+            }
+          >
+        ),
+        startWith({})
+      )
+    )
+  ),
+  {}
+);
+
 const shouldUpdateRepo$ = defer(() => repoEvents$).pipe(
   filter((v) =>
     v.paths.some(
@@ -126,28 +251,109 @@ const shouldUpdateRepo$ = defer(() => repoEvents$).pipe(
   )
 );
 
-const commitEvent$ = repoPath$.pipe(
+export type GraphLayout = "Spread" | "Compact";
+export const [changeGraphLayout$, changeGraphLayout] =
+  createSignal<GraphLayout>();
+// Kept for each repo
+export const graphLayout$ = repoPath$.pipeState(
+  filter(isNotNullish),
   switchMap((path) =>
-    shouldUpdateRepo$.pipe(
-      exhaustMap(() =>
-        concat(
-          [{ type: "start" as const }],
-          streamCommand$<Omit<PositionedCommit, "id">>("get_commits", {
-            path,
-          }).pipe(
-            map((payload) => ({
-              type: "update" as const,
+    concat(
+      from(invoke<GraphLayout>("get_graph_layout", { path })),
+      changeGraphLayout$.pipe(
+        tap((layout) => invoke("set_graph_layout", { path, layout }))
+      )
+    ).pipe(map((layout) => ({ path, layout })))
+  )
+);
+
+interface StreamedGraph {
+  batches: number;
+  tips: string[] | null; // null if it was cancelled
+}
+
+type CommitsUpdate =
+  | { type: "Reload" }
+  | {
+      type: "Update";
+      payload: {
+        tips: string[];
+        rows: Array<Omit<PositionedCommit, "id">>;
+        replaced: number; // old rows the new ones go in place of
+      };
+    };
+
+const withId = (commit: Omit<PositionedCommit, "id">): PositionedCommit => ({
+  ...commit,
+  id: commit.commit.id,
+});
+
+const commitEvent$ = graphLayout$.pipe(
+  switchMap(({ path, layout }) => {
+    // What the graph we have was positioned for, so it can be updated from there
+    let tips: string[] | null = null;
+
+    const load$ = concat(
+      [{ type: "start" as const }],
+      streamCommandResult$<
+        Array<Omit<PositionedCommit, "id">>,
+        StreamedGraph
+      >(
+        "get_commits",
+        { path, options: { layout } },
+        { ack: "ack_commits", cancel: "cancel_commits" },
+        (result) => result.batches
+      ).pipe(
+        mergeMap((event) => {
+          if (event.type === "result") {
+            tips = event.payload.tips;
+            return [];
+          }
+          return event.payload.map((payload) => ({
+            type: "update" as const,
+            payload: withId(payload),
+          }));
+        })
+      ),
+      [{ type: "end" as const }]
+    );
+
+    const update$ = (previousTips: string[]) =>
+      defer(() =>
+        invoke<CommitsUpdate>("update_commits", {
+          path,
+          previousTips,
+          options: { layout },
+        })
+      ).pipe(
+        switchMap((update) => {
+          if (update.type === "Reload") return load$;
+          tips = update.payload.tips;
+          return [
+            {
+              type: "patch" as const,
               payload: {
-                ...payload,
-                id: payload.commit.id,
+                rows: update.payload.rows.map(withId),
+                replaced: update.payload.replaced,
               },
-            }))
-          ),
-          [{ type: "end" as const }]
-        )
-      )
-    )
-  ),
+            },
+          ];
+        }),
+        catchError((err) => {
+          console.error(err);
+          return load$;
+        })
+      );
+
+    return shouldUpdateRepo$.pipe(
+      exhaustMap(() => {
+        const previousTips = tips;
+        // Until it's done, the graph doesn't match any tips
+        tips = null;
+        return previousTips ? update$(previousTips) : load$;
+      })
+    );
+  }),
   share()
 );
 
@@ -170,6 +376,16 @@ export const commits$ = state(
           case "end":
             acc.array.length = acc.i;
             return acc;
+          case "patch": {
+            const array = [
+              ...event.payload.rows,
+              ...acc.array.slice(event.payload.replaced),
+            ];
+            return {
+              array,
+              i: array.length,
+            };
+          }
         }
       },
       {
@@ -195,10 +411,15 @@ export const commitLookup$ = commits$.pipeState(
   }),
   switchMap((initialMap) =>
     commitEvent$.pipe(
-      filter((event) => event.type === "update"),
+      filter((event) => event.type === "update" || event.type === "patch"),
       map((event) => {
-        if (event.type !== "update") return initialMap;
-        initialMap[event.payload.commit.id] = event.payload;
+        if (event.type === "update") {
+          initialMap[event.payload.commit.id] = event.payload;
+        } else if (event.type === "patch") {
+          event.payload.rows.forEach(
+            (row) => (initialMap[row.commit.id] = row)
+          );
+        }
         return initialMap;
       }),
       startWith(initialMap)
@@ -208,36 +429,71 @@ export const commitLookup$ = commits$.pipeState(
   map((v) => ({ ...v }))
 );
 
+export interface UpstreamInfo {
+  name: string;
+  gone: boolean;
+  ahead: number | null; // null if it couldn't be counted, or it's gone
+  behind: number | null;
+}
 export interface LocalRef {
   id: string;
   name: string;
   is_head: boolean;
+  upstream: UpstreamInfo | null;
+}
+export interface RemoteHeadRef {
+  id: string;
+  remote: string;
+  name: string; // branch of the remote it points to
+}
+export interface TagRef {
+  id: string; // of the commit, even for annotated tags
+  name: string;
+  annotated: boolean;
+  tagger: SignatureInfo | null;
+  message: string | null;
+  signature: SignatureStatus;
 }
 export interface RemoteRef {
   id: string;
   remote: string;
   name: string;
 }
+export interface StashRef {
+  id: string;
+  index: number;
+  message: string;
+}
 
 export enum RefType {
   Head = "Head",
+  UnbornHead = "UnbornHead",
   LocalBranch = "LocalBranch",
   RemoteBranch = "RemoteBranch",
+  RemoteHead = "RemoteHead",
   Tag = "Tag",
+  Stash = "Stash",
 }
 
 type RustRef =
   | { type: RefType.Head; payload: string }
+  | { type: RefType.UnbornHead; payload: string }
   | { type: RefType.LocalBranch; payload: LocalRef }
   | { type: RefType.RemoteBranch; payload: RemoteRef }
-  | { type: RefType.Tag; payload: LocalRef };
+  | { type: RefType.RemoteHead; payload: RemoteHeadRef }
+  | { type: RefType.Tag; payload: TagRef }
+  | { type: RefType.Stash; payload: StashRef };
 
 export interface Refs {
   head: string | null;
+  // Branch HEAD is on when it doesn't have any commit yet
+  unbornHead: string | null;
   activeBranch: LocalRef | null;
   local: Array<LocalRef>;
   remotes: Record<string, Array<RemoteRef>>;
-  tags: Array<LocalRef>;
+  remoteHeads: Record<string, RemoteHeadRef>;
+  tags: Array<TagRef>;
+  stashes: Array<StashRef>;
 }
 
 const getRefs$ = (path: string) => invoke<Array<RustRef>>("get_refs", { path });
@@ -259,10 +515,13 @@ export const refs$ = repoPath$.pipeState(
   map((refs) => {
     const result: Refs = {
       head: null,
+      unbornHead: null,
       activeBranch: null,
       local: [],
       remotes: {},
+      remoteHeads: {},
       tags: [],
+      stashes: [],
     };
 
     refs.forEach((ref) => {
@@ -270,6 +529,9 @@ export const refs$ = repoPath$.pipeState(
         case "Head":
           result.head = ref.payload;
           break;
+        case "UnbornHead":
+          result.unbornHead = ref.payload;
+          break;
         case "LocalBranch":
           result.local.push(ref.payload);
           if (ref.payload.is_head) {
@@ -281,9 +543,15 @@ export const refs$ = repoPath$.pipeState(
             result.remotes[ref.payload.remote] || [];
           result.remotes[ref.payload.remote].push(ref.payload);
           break;
+        case "RemoteHead":
+          result.remoteHeads[ref.payload.remote] = ref.payload;
+          break;
         case "Tag":
           result.tags.push(ref.payload);
           break;
+        case "Stash":
+          result.stashes.push(ref.payload);
+          break;
       }
     });
 
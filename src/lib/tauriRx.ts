@@ -1,12 +1,15 @@
 import {
   combineLatest,
+  concat,
   connect,
   defer,
   filter,
   finalize,
   map,
+  mergeMap,
   Observable,
   scan,
+  shareReplay,
   startWith,
   Subject,
   switchMap,
@@ -29,34 +32,81 @@ export const listen$ = <T>(evt: string) =>
     };
   });
 
-export const streamCommand$ = <T>(
+export interface StreamControl {
+  // Command called after every message is received, for flow control
+  ack?: string;
+  // Command called when unsubscribing before the stream has completed
+  cancel?: string;
+}
+
+export type StreamEvent<T, R> =
+  | { type: "message"; payload: T }
+  | { type: "result"; payload: R };
+
+// Like `streamCommand$`, for commands that return more than the amount of
+// messages emitted. What the command returned comes last.
+export const streamCommandResult$ = <T, R>(
   command: string,
-  payload?: Record<string, unknown>
+  payload: Record<string, unknown> | undefined,
+  control: StreamControl,
+  // Amount of messages emitted, from what the command returned
+  count: (result: R) => number
 ) =>
   defer(() => {
     const id = v4();
     const messages$ = new Subject<void>();
+    let completed = false;
     const request$ = defer(() =>
-      invoke<number>(command, { ...payload, correlationId: id })
+      invoke<R>(command, { ...payload, correlationId: id })
+    ).pipe(
+      tap(() => (completed = true)),
+      shareReplay(1)
     );
 
-    return listen$<T>(`${command}-stream-${id}`).pipe(
-      tap(() => messages$.next()),
-      map(({ payload }) => payload),
-      takeUntil(
-        // Stop when we've received all messages
-        combineLatest([
-          messages$.pipe(
-            scan((v) => v + 1, 0),
-            startWith(0)
-          ),
-          request$,
-        ]).pipe(filter(([v, messages]) => v === messages))
+    return concat(
+      listen$<T>(`${command}-stream-${id}`).pipe(
+        tap(() => messages$.next()),
+        tap(() => {
+          if (control.ack) invoke(control.ack, { correlationId: id });
+        }),
+        map(
+          ({ payload }): StreamEvent<T, R> => ({ type: "message", payload })
+        ),
+        takeUntil(
+          // Stop when we've received all messages
+          combineLatest([
+            messages$.pipe(
+              scan((v) => v + 1, 0),
+              startWith(0)
+            ),
+            request$.pipe(map(count)),
+          ]).pipe(filter(([v, messages]) => v === messages))
+        )
       ),
-      finalize(() => messages$.complete())
+      request$.pipe(
+        map(
+          (result): StreamEvent<T, R> => ({ type: "result", payload: result })
+        )
+      )
+    ).pipe(
+      finalize(() => {
+        messages$.complete();
+        if (!completed && control.cancel) {
+          invoke(control.cancel, { correlationId: id });
+        }
+      })
     );
   });
 
+export const streamCommand$ = <T>(
+  command: string,
+  payload?: Record<string, unknown>,
+  control: StreamControl = {}
+) =>
+  streamCommandResult$<T, number>(command, payload, control, (v) => v).pipe(
+    mergeMap((event) => (event.type === "message" ? [event.payload] : []))
+  );
+
 export const waitWithLatestFrom =
   <T, O>(obs$: Observable<O>) =>
   (source$: Observable<T>) =>